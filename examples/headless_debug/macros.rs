@@ -25,4 +25,12 @@ macro_rules! comment {
             .join(", ");
         format!("{{{}}}", inner)
     }};
+    ($dbg:expr, $targets:expr) => {{
+        let inner = $targets
+            .iter()
+            .map(|x| $dbg.format_address(*x))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{{{}}}", inner)
+    }};
 }
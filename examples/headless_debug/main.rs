@@ -114,6 +114,9 @@ fn main() {
     let mut call_sites: HashMap<u64, CallSiteInfo> = HashMap::new();
     let mut hit_count = 0;
 
+    // registers/memory are read repeatedly per hit, serve them from the per-stop cache
+    dbg.enable_cache();
+
     loop {
         let reason = dbg.go_and_wait();
 
@@ -125,8 +128,7 @@ fn main() {
         let runtime_ip = dbg.ip();
 
         if let Some(call) = bp_to_call.get(&runtime_ip) {
-            let rax_bytes = dbg.get_register_value("rax");
-            let rax = u64::from_le_bytes(rax_bytes[..8].try_into().unwrap_or([0; 8]));
+            let rax = dbg.reg_u64("rax").unwrap_or(0);
 
             let target = read_u64(&bv, call.addr + call.offset as u64).unwrap_or(0);
             
@@ -143,6 +145,7 @@ fn main() {
             let entry = call_sites.entry(file_addr).or_insert_with(|| CallSiteInfo {
                 addr: file_addr,
                 called_methods: Vec::new(),
+                comment: String::new(),
                 observations: Vec::new(),
             });
 
@@ -154,18 +157,24 @@ fn main() {
                 });
 
                 entry.called_methods.push(target);
+                entry.comment = comment!(dbg, entry.called_methods);
                 hit_count += 1;
 
                 println!(
-                    "HIT {}: file=0x{:x} vtable=0x{:x} methods={} offset=0x{:x}",
-                    hit_count, file_addr, rax, method_count, call.offset
+                    "HIT {}: {} file=0x{:x} vtable={} methods={} offset=0x{:x}",
+                    hit_count,
+                    dbg.format_address(runtime_ip),
+                    file_addr,
+                    dbg.format_address(rax),
+                    method_count,
+                    call.offset
                 );
             }
         }
     }
 
     for (file_addr, info) in &call_sites {
-        bv.set_comment_at(*file_addr, comment!(info.called_methods).as_str());
+        bv.set_comment_at(*file_addr, info.comment.as_str());
     }
 
 
@@ -30,6 +30,8 @@ pub struct VTableObservation {
 pub struct CallSiteInfo {
     pub addr: u64,
     pub called_methods: Vec<u64>,
+    /// `called_methods` symbolized while the process was alive
+    pub comment: String,
     pub observations: Vec<VTableObservation>,
 }
 
@@ -0,0 +1,259 @@
+// architecture and calling convention aware register accessors
+
+use crate::types::{core_string, CoreFunction};
+use crate::DebuggerController;
+use binaryninjacore_sys as sys;
+use binaryninjacore_sys::BNEndianness;
+
+const INVALID_REGISTER: u32 = 0xffff_ffff;
+
+/// Owned calling convention reference
+pub(crate) struct CallingConvention {
+    handle: *mut sys::BNCallingConvention,
+}
+
+impl Drop for CallingConvention {
+    fn drop(&mut self) {
+        unsafe { sys::BNFreeCallingConvention(self.handle) }
+    }
+}
+
+impl CallingConvention {
+    /// Wrap an owned reference, returns None for null
+    pub(crate) unsafe fn from_raw(handle: *mut sys::BNCallingConvention) -> Option<Self> {
+        if handle.is_null() {
+            None
+        } else {
+            Some(Self { handle })
+        }
+    }
+
+    pub(crate) fn handle(&self) -> *mut sys::BNCallingConvention {
+        self.handle
+    }
+
+    pub(crate) fn name(&self) -> String {
+        unsafe { core_string(sys::BNGetCallingConventionName(self.handle)) }.unwrap_or_default()
+    }
+
+    /// Integer argument registers in argument order
+    pub(crate) fn integer_argument_registers(&self) -> Vec<u32> {
+        let mut count = 0usize;
+        unsafe {
+            let regs = sys::BNGetIntegerArgumentRegisters(self.handle, &mut count);
+            if regs.is_null() {
+                return Vec::new();
+            }
+            let result = std::slice::from_raw_parts(regs, count).to_vec();
+            sys::BNFreeRegisterList(regs);
+            result
+        }
+    }
+
+    pub(crate) fn integer_return_register(&self) -> Option<u32> {
+        valid(unsafe { sys::BNGetIntegerReturnValueRegister(self.handle) })
+    }
+
+    pub(crate) fn float_return_register(&self) -> Option<u32> {
+        valid(unsafe { sys::BNGetFloatReturnValueRegister(self.handle) })
+    }
+
+    /// Whether the caller reserves stack slots for the register arguments (win64 shadow space)
+    pub(crate) fn stack_reserved_for_argument_registers(&self) -> bool {
+        unsafe { sys::BNIsStackReservedForArgumentRegisters(self.handle) }
+    }
+}
+
+fn valid(reg: u32) -> Option<u32> {
+    (reg != INVALID_REGISTER).then_some(reg)
+}
+
+/// Name of an architecture register
+pub(crate) fn register_name(arch: *mut sys::BNArchitecture, reg: u32) -> Option<String> {
+    valid(reg)?;
+    unsafe { core_string(sys::BNGetArchitectureRegisterName(arch, reg)) }
+        .filter(|name| !name.is_empty())
+}
+
+/// Conventional frame pointer, the architecture plugins don't expose one
+fn frame_pointer_name(arch: &str) -> Option<&'static str> {
+    Some(match arch {
+        "x86_64" => "rbp",
+        "x86" => "ebp",
+        "aarch64" => "x29",
+        "armv7" | "armv7eb" => "r11",
+        "thumb2" | "thumb2eb" => "r7",
+        "mips32" | "mipsel32" | "mips64" => "fp",
+        "ppc" | "ppc_le" | "ppc64" | "ppc64_le" => "r31",
+        _ => return None,
+    })
+}
+
+impl DebuggerController {
+    /// Name of the target architecture, e.g. "x86_64"
+    pub fn arch_name(&self) -> Option<String> {
+        let arch = self.arch()?;
+        unsafe { core_string(sys::BNGetArchitectureName(arch)) }
+    }
+
+    pub(crate) fn address_size(&self) -> usize {
+        self.arch()
+            .map(|arch| unsafe { sys::BNGetArchitectureAddressSize(arch) })
+            .unwrap_or(8)
+    }
+
+    fn is_big_endian(&self) -> bool {
+        self.arch().is_some_and(|arch| unsafe {
+            sys::BNGetArchitectureEndianness(arch) == BNEndianness::BigEndian
+        })
+    }
+
+    /// Read a target pointer sized value
+    pub(crate) fn read_pointer(&self, address: u64) -> Option<u64> {
+        let size = self.address_size().min(8);
+        let bytes = self.read_memory(address, size)?;
+        let mut buf = [0u8; 8];
+        if self.is_big_endian() {
+            buf[8 - size..].copy_from_slice(&bytes);
+            Some(u64::from_be_bytes(buf))
+        } else {
+            buf[..size].copy_from_slice(&bytes);
+            Some(u64::from_le_bytes(buf))
+        }
+    }
+
+    /// Encode a target pointer sized value
+    pub(crate) fn pointer_bytes(&self, value: u64) -> Vec<u8> {
+        let size = self.address_size().min(8);
+        if self.is_big_endian() {
+            value.to_be_bytes()[8 - size..].to_vec()
+        } else {
+            value.to_le_bytes()[..size].to_vec()
+        }
+    }
+
+    /// Write a target pointer sized value
+    pub(crate) fn write_pointer(&self, address: u64, value: u64) -> bool {
+        self.write_memory(address, &self.pointer_bytes(value))
+    }
+
+    /// Calling convention of the function at the IP, falling back to the
+    /// platform and then the architecture default.
+    pub(crate) fn calling_convention(&self) -> Option<CallingConvention> {
+        let ip = self.ip();
+        if let Some(view) = self.analysis_view(ip) {
+            if let Some(cc) =
+                CoreFunction::containing(&view, ip).and_then(|f| f.calling_convention())
+            {
+                return Some(cc);
+            }
+            unsafe {
+                let platform = sys::BNGetDefaultPlatform(view.handle());
+                if !platform.is_null() {
+                    let cc = CallingConvention::from_raw(
+                        sys::BNGetPlatformDefaultCallingConvention(platform),
+                    );
+                    sys::BNFreePlatform(platform);
+                    if cc.is_some() {
+                        return cc;
+                    }
+                }
+            }
+        }
+        let arch = self.arch()?;
+        unsafe { CallingConvention::from_raw(sys::BNGetArchitectureDefaultCallingConvention(arch)) }
+    }
+
+    /// Name of the calling convention used by `arg`/`return_value`
+    pub fn calling_convention_name(&self) -> Option<String> {
+        Some(self.calling_convention()?.name())
+    }
+
+    /// Program counter
+    pub fn pc(&self) -> u64 {
+        self.ip()
+    }
+
+    /// Stack pointer, read through the architecture's stack pointer register
+    pub fn sp(&self) -> u64 {
+        self.arch()
+            .and_then(|arch| {
+                register_name(arch, unsafe {
+                    sys::BNGetArchitectureStackPointerRegister(arch)
+                })
+            })
+            .and_then(|name| self.reg_u64(&name))
+            .unwrap_or_else(|| self.stack_pointer())
+    }
+
+    /// Frame pointer, None on architectures without a conventional one
+    pub fn fp(&self) -> Option<u64> {
+        let name = frame_pointer_name(&self.arch_name()?)?;
+        self.reg_u64(name)
+    }
+
+    fn return_register(&self) -> Option<String> {
+        let arch = self.arch()?;
+        let cc = self.calling_convention()?;
+        register_name(arch, cc.integer_return_register()?)
+    }
+
+    /// Integer return value register, meaningful after the function returned
+    pub fn return_value(&self) -> Option<u64> {
+        self.reg_u64(&self.return_register()?)
+    }
+
+    pub fn set_return_value(&self, value: u64) -> bool {
+        match self.return_register() {
+            Some(name) => self.set_reg_u64(&name, value),
+            None => false,
+        }
+    }
+
+    /// Where integer argument `n` lives
+    fn arg_location(&self, n: usize) -> Option<ArgLocation> {
+        let arch = self.arch()?;
+        let cc = self.calling_convention()?;
+        let regs = cc.integer_argument_registers();
+        if let Some(&reg) = regs.get(n) {
+            return register_name(arch, reg).map(ArgLocation::Register);
+        }
+
+        let slot = self.address_size() as u64;
+        let mut offset = 0;
+        // without a link register the call pushed the return address
+        if valid(unsafe { sys::BNGetArchitectureLinkRegister(arch) }).is_none() {
+            offset += slot;
+        }
+        if cc.stack_reserved_for_argument_registers() {
+            offset += regs.len() as u64 * slot;
+        }
+        offset += (n - regs.len()) as u64 * slot;
+        Some(ArgLocation::Stack(self.sp().wrapping_add(offset)))
+    }
+
+    /// Integer argument `n` (0 based) of the current function.
+    ///
+    /// Register arguments follow the calling convention, stack arguments are
+    /// computed from the stack pointer and are only valid at the function entry.
+    pub fn arg(&self, n: usize) -> Option<u64> {
+        match self.arg_location(n)? {
+            ArgLocation::Register(name) => self.reg_u64(&name),
+            ArgLocation::Stack(address) => self.read_pointer(address),
+        }
+    }
+
+    /// Overwrite integer argument `n`, see `arg`
+    pub fn set_arg(&self, n: usize, value: u64) -> bool {
+        match self.arg_location(n) {
+            Some(ArgLocation::Register(name)) => self.set_reg_u64(&name, value),
+            Some(ArgLocation::Stack(address)) => self.write_pointer(address, value),
+            None => false,
+        }
+    }
+}
+
+enum ArgLocation {
+    Register(String),
+    Stack(u64),
+}
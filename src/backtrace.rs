@@ -0,0 +1,205 @@
+// call stacks resolved against the analysis of the live view
+
+use crate::abi::register_name;
+use crate::types::{CoreFunction, CoreView};
+use crate::values::{TypedValue, ValueLocation};
+use crate::DebuggerController;
+use binaryninjacore_sys as sys;
+use binaryninjacore_sys::BNVariableSourceType;
+use std::fmt;
+
+/// A frame variable of the MLIL function and its current value
+#[derive(Debug, Clone)]
+pub struct FrameVariable {
+    pub name: String,
+    pub type_name: String,
+    pub location: ValueLocation,
+    /// None if unreadable, or for register variables of outer frames whose
+    /// registers were not preserved by the callee
+    pub value: Option<TypedValue>,
+}
+
+impl fmt::Display for FrameVariable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} ({})", self.type_name, self.name, self.location)?;
+        match &self.value {
+            Some(value) => write!(f, " = {}", value),
+            None => write!(f, " = <unavailable>"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BacktraceFrame {
+    pub index: usize,
+    pub pc: u64,
+    pub sp: u64,
+    pub fp: u64,
+    /// Module containing the pc and the pc's offset in it
+    pub module: Option<(String, u64)>,
+    /// Short demangled name of the Binary Ninja function
+    pub function: Option<String>,
+    /// Full demangled name, including parameters for C++ symbols
+    pub demangled: Option<String>,
+    pub function_start: Option<u64>,
+    /// HLIL statement at the pc, the call for outer frames
+    pub hlil: Option<String>,
+    pub variables: Vec<FrameVariable>,
+}
+
+impl fmt::Display for BacktraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} 0x{:x}", self.index, self.pc)?;
+        match (&self.demangled, self.function_start) {
+            (Some(name), Some(start)) => write!(f, " in {}+0x{:x}", name, self.pc - start)?,
+            (Some(name), None) => write!(f, " in {}", name)?,
+            _ => {}
+        }
+        if let Some((module, offset)) = &self.module {
+            write!(f, " ({}+0x{:x})", module, offset)?;
+        }
+        if let Some(hlil) = &self.hlil {
+            write!(f, "\n    {}", hlil)?;
+        }
+        for var in &self.variables {
+            write!(f, "\n    {}", var)?;
+        }
+        Ok(())
+    }
+}
+
+/// Start of the instruction ending at `return_address`, the call of an outer frame
+fn call_site(view: &CoreView, func: &CoreFunction, return_address: u64) -> Option<u64> {
+    unsafe {
+        let arch = func.arch();
+        let block = sys::BNGetFunctionBasicBlockAtAddress(func.handle(), arch, return_address - 1);
+        if block.is_null() {
+            return None;
+        }
+        let mut address = sys::BNGetBasicBlockStart(block);
+        sys::BNFreeBasicBlock(block);
+        while address < return_address {
+            let len = sys::BNGetInstructionLength(view.handle(), arch, address) as u64;
+            if len == 0 {
+                return None;
+            }
+            if address + len >= return_address {
+                return Some(address);
+            }
+            address += len;
+        }
+        None
+    }
+}
+
+impl DebuggerController {
+    /// Call stack of the active thread, resolved against the live view.
+    ///
+    /// Each frame gets the containing function, its module-relative offset,
+    /// the HLIL statement being executed and the function's variables.
+    /// Stack variables are read relative to the stack pointer at the function
+    /// entry, which is recovered from Binary Ninja's stack offset analysis at
+    /// the frame's pc. Register variables are only read for the innermost frame.
+    pub fn backtrace(&self) -> Vec<BacktraceFrame> {
+        let frames = self.frames_of_thread(self.active_thread().tid);
+        frames
+            .into_iter()
+            .map(|frame| {
+                let mut result = BacktraceFrame {
+                    index: frame.index,
+                    pc: frame.pc,
+                    sp: frame.sp,
+                    fp: frame.fp,
+                    module: self.absolute_to_relative(frame.pc),
+                    function: None,
+                    demangled: None,
+                    function_start: None,
+                    hlil: None,
+                    variables: Vec::new(),
+                };
+                let view = self.analysis_view(frame.pc);
+                let func = view
+                    .as_ref()
+                    .and_then(|view| CoreFunction::at(view, frame.pc));
+                let (Some(view), Some(func)) = (&view, func) else {
+                    // without analysis, at least name the closest symbol
+                    if let Some(symbol) = self.symbolize(frame.pc) {
+                        result.function_start =
+                            symbol.symbol.is_some().then(|| frame.pc - symbol.offset);
+                        result.function = symbol.symbol.clone();
+                        result.demangled = symbol.symbol;
+                    }
+                    return result;
+                };
+                result.function = Some(func.short_name());
+                result.demangled = Some(func.name());
+                result.function_start = Some(func.start());
+
+                let innermost = frame.index == 0;
+                let statement = if innermost {
+                    Some(frame.pc)
+                } else {
+                    call_site(view, &func, frame.pc)
+                };
+                result.hlil = statement.and_then(|address| func.hlil_line(address));
+                result.variables = self.frame_variables(view, &func, frame.pc, frame.sp, innermost);
+                result
+            })
+            .collect()
+    }
+
+    fn frame_variables(
+        &self,
+        view: &CoreView,
+        func: &CoreFunction,
+        pc: u64,
+        sp: u64,
+        innermost: bool,
+    ) -> Vec<FrameVariable> {
+        let entry_sp = func
+            .stack_offset_at(pc)
+            .map(|offset| sp.wrapping_sub(offset as u64));
+        let mut variables = Vec::new();
+        for (var, name, ty) in func.variables() {
+            let Some(ty) = ty else {
+                continue;
+            };
+            let type_name = ty.name();
+            let size = ty.width() as usize;
+            let (location, bytes) = match var.type_ {
+                BNVariableSourceType::StackVariableSourceType => {
+                    let Some(entry_sp) = entry_sp else {
+                        continue;
+                    };
+                    match self.read_variable(func, &var, size, entry_sp) {
+                        Some((location, bytes)) => (location, Some(bytes)),
+                        None => {
+                            let address = entry_sp.wrapping_add(var.storage as u64);
+                            (ValueLocation::Stack(address), None)
+                        }
+                    }
+                }
+                BNVariableSourceType::RegisterVariableSourceType if innermost => {
+                    let Some((location, bytes)) = self.read_variable(func, &var, size, 0) else {
+                        continue;
+                    };
+                    (location, Some(bytes))
+                }
+                BNVariableSourceType::RegisterVariableSourceType => {
+                    let Some(reg) = register_name(func.arch(), var.storage as u32) else {
+                        continue;
+                    };
+                    (ValueLocation::Register(reg), None)
+                }
+                BNVariableSourceType::FlagVariableSourceType => continue,
+            };
+            variables.push(FrameVariable {
+                name,
+                type_name,
+                location,
+                value: bytes.map(|bytes| self.decode_value(view, ty, &bytes, 0)),
+            });
+        }
+        variables
+    }
+}
@@ -0,0 +1,208 @@
+// opt-in register and memory cache, valid while the target is stopped
+
+use crate::memory::PAGE_SIZE;
+use crate::{BNDebuggerEventType, DebugRegister, DebuggerController};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Reads larger than this bypass the cache, bulk dumps would only evict it
+const MAX_CACHED_READ: usize = 64 * PAGE_SIZE as usize;
+
+#[derive(Default)]
+struct CacheState {
+    registers: Option<Vec<DebugRegister>>,
+    register_values: HashMap<String, Vec<u8>>,
+    // None marks a page known to be unreadable
+    pages: HashMap<u64, Option<Arc<[u8]>>>,
+}
+
+#[derive(Default)]
+pub(crate) struct StopCache {
+    enabled: AtomicBool,
+    // bumped on every invalidation so in-flight reads don't repopulate stale data
+    generation: AtomicU64,
+    callback: Mutex<Option<usize>>,
+    state: Mutex<CacheState>,
+}
+
+impl StopCache {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    /// Bumped by every invalidation, i.e. on every resume and stop
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn take_callback(&self) -> Option<usize> {
+        self.callback.lock().unwrap().take()
+    }
+
+    pub(crate) fn invalidate(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        *self.state.lock().unwrap() = CacheState::default();
+    }
+
+    pub(crate) fn invalidate_registers(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        // called from ThreadGuard::drop, must not panic while unwinding
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.registers = None;
+        state.register_values.clear();
+    }
+
+    fn invalidates(event: BNDebuggerEventType) -> bool {
+        use BNDebuggerEventType::*;
+        matches!(
+            event,
+            LaunchEventType
+                | ResumeEventType
+                | StepIntoEventType
+                | StepOverEventType
+                | StepReturnEventType
+                | StepToEventType
+                | RestartEventType
+                | AttachEventType
+                | DetachEventType
+                | ConnectEventType
+                | AdapterStoppedEventType
+                | AdapterTargetExitedEventType
+                | TargetStoppedEventType
+                | TargetExitedEventType
+                | DetachedEventType
+                | ActiveThreadChangedEvent
+                | RegisterChangedEvent
+                | ThreadStateChangedEvent
+                | ForceMemoryCacheUpdateEvent
+        )
+    }
+
+    pub(crate) fn registers(
+        &self,
+        fetch: impl FnOnce() -> Vec<DebugRegister>,
+    ) -> Vec<DebugRegister> {
+        if let Some(regs) = &self.state.lock().unwrap().registers {
+            return regs.clone();
+        }
+        let generation = self.generation.load(Ordering::Acquire);
+        let regs = fetch();
+        let mut state = self.state.lock().unwrap();
+        if generation == self.generation.load(Ordering::Acquire) {
+            state.registers = Some(regs.clone());
+        }
+        regs
+    }
+
+    pub(crate) fn register_value(&self, name: &str, fetch: impl FnOnce() -> Vec<u8>) -> Vec<u8> {
+        if let Some(value) = self.state.lock().unwrap().register_values.get(name) {
+            return value.clone();
+        }
+        let generation = self.generation.load(Ordering::Acquire);
+        let value = fetch();
+        let mut state = self.state.lock().unwrap();
+        if generation == self.generation.load(Ordering::Acquire) {
+            state.register_values.insert(name.to_owned(), value.clone());
+        }
+        value
+    }
+
+    /// Serve a read from cached pages, fetching the missing ones a page at a time
+    pub(crate) fn read(
+        &self,
+        address: u64,
+        size: usize,
+        fetch: impl Fn(u64, usize) -> Option<Vec<u8>>,
+    ) -> Option<Vec<u8>> {
+        if size > MAX_CACHED_READ {
+            return fetch(address, size);
+        }
+        let end = address.checked_add(size as u64)?;
+        let mut result = Vec::with_capacity(size);
+        let mut page = address / PAGE_SIZE * PAGE_SIZE;
+        while page < end {
+            let cached = self.state.lock().unwrap().pages.get(&page).cloned();
+            let data = match cached {
+                Some(data) => data,
+                None => {
+                    let generation = self.generation.load(Ordering::Acquire);
+                    let data: Option<Arc<[u8]>> = fetch(page, PAGE_SIZE as usize).map(|d| d.into());
+                    let mut state = self.state.lock().unwrap();
+                    if generation == self.generation.load(Ordering::Acquire) {
+                        state.pages.insert(page, data.clone());
+                    }
+                    data
+                }
+            };
+            let data = data?;
+
+            let from = address.max(page) - page;
+            let to = end.min(page + PAGE_SIZE) - page;
+            result.extend_from_slice(data.get(from as usize..to as usize)?);
+            page += PAGE_SIZE;
+        }
+        Some(result)
+    }
+
+    /// Apply a successful memory write to the cached pages
+    pub(crate) fn write(&self, address: u64, data: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        let end = address + data.len() as u64;
+        let mut page = address / PAGE_SIZE * PAGE_SIZE;
+        while page < end {
+            if let Some(Some(cached)) = state.pages.get(&page) {
+                let mut updated = cached.to_vec();
+                let from = address.max(page);
+                let to = end.min(page + PAGE_SIZE);
+                updated[(from - page) as usize..(to - page) as usize]
+                    .copy_from_slice(&data[(from - address) as usize..(to - address) as usize]);
+                state.pages.insert(page, Some(updated.into()));
+            }
+            page += PAGE_SIZE;
+        }
+    }
+}
+
+impl DebuggerController {
+    /// Enable the register and memory cache.
+    ///
+    /// While the target is stopped repeated register and memory reads are
+    /// served without going through the adapter. The cache is dropped on every
+    /// resume, stop, thread switch and `ForceMemoryCacheUpdateEvent`; writes
+    /// made through this controller update it in place.
+    pub fn enable_cache(&self) {
+        let cache = &self.inner.cache;
+        let mut callback = cache.callback.lock().unwrap();
+        if callback.is_none() {
+            let events = Arc::clone(cache);
+            *callback = Some(
+                self.register_event_callback("rust-stop-cache", move |event| {
+                    if StopCache::invalidates(event.event_type) {
+                        events.invalidate();
+                    }
+                }),
+            );
+        }
+        cache.invalidate();
+        cache.enabled.store(true, Ordering::Release);
+    }
+
+    pub fn disable_cache(&self) {
+        let cache = &self.inner.cache;
+        cache.enabled.store(false, Ordering::Release);
+        if let Some(index) = cache.take_callback() {
+            self.remove_event_callback(index);
+        }
+        cache.invalidate();
+    }
+
+    pub fn is_cache_enabled(&self) -> bool {
+        self.inner.cache.is_enabled()
+    }
+
+    /// Drop everything cached, e.g. after modifying the target behind the controller's back
+    pub fn invalidate_cache(&self) {
+        self.inner.cache.invalidate();
+    }
+}
@@ -0,0 +1,201 @@
+// call functions of the target from the debugger (inferior calls)
+
+use crate::abi::register_name;
+use crate::registers::MAX_REGISTER_SIZE;
+use crate::types::{CoreFunction, CoreView};
+use crate::{ffi, BNDebugStopReason, DebugRegister, DebuggerController};
+use binaryninjacore_sys as sys;
+use std::ffi::CString;
+
+/// Skipped below the current stack pointer, covers the x86_64 SysV red zone
+const RED_ZONE: u64 = 256;
+const STACK_ALIGNMENT: u64 = 16;
+
+/// Function to call, by address or symbol name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallTarget<'a> {
+    Address(u64),
+    Symbol(&'a str),
+}
+
+impl From<u64> for CallTarget<'_> {
+    fn from(address: u64) -> Self {
+        Self::Address(address)
+    }
+}
+
+impl<'a> From<&'a str> for CallTarget<'a> {
+    fn from(name: &'a str) -> Self {
+        Self::Symbol(name)
+    }
+}
+
+fn symbol_address(view: &CoreView, name: &str) -> Option<u64> {
+    let name = CString::new(name).ok()?;
+    unsafe {
+        let mut count = 0usize;
+        let syms =
+            sys::BNGetSymbolsByName(view.handle(), name.as_ptr(), &mut count, std::ptr::null());
+        if syms.is_null() {
+            return None;
+        }
+        let address = std::slice::from_raw_parts(syms, count)
+            .first()
+            .map(|&sym| sys::BNGetSymbolAddress(sym));
+        sys::BNFreeSymbolList(syms, count);
+        address
+    }
+}
+
+impl DebuggerController {
+    /// Call a function in the target with integer/pointer arguments and return
+    /// its integer return value.
+    ///
+    /// All registers and the stack below the stack pointer are saved and restored
+    /// afterwards, so the target continues as if nothing happened. Arguments are
+    /// placed according to the callee's calling convention and the return address
+    /// points at the program entry point, which is used as the trap location.
+    /// Only the active thread returning there ends the call, other threads
+    /// hitting the trap are skipped.
+    ///
+    /// Returns None, after restoring the state, if the call stops anywhere else
+    /// (a breakpoint inside the callee, a crash, ...).
+    pub fn call_function<'a>(
+        &self,
+        target: impl Into<CallTarget<'a>>,
+        args: &[u64],
+    ) -> Option<u64> {
+        if !self.is_connected() || self.is_running() {
+            log::warn!("the target must be stopped to call functions");
+            return None;
+        }
+        let view = self.live_view()?;
+        let address = match target.into() {
+            CallTarget::Address(address) => address,
+            CallTarget::Symbol(name) => match symbol_address(&view, name) {
+                Some(address) => address,
+                None => {
+                    log::warn!("no symbol named {}", name);
+                    return None;
+                }
+            },
+        };
+        let trap = unsafe { sys::BNGetEntryPoint(view.handle()) };
+        let arch = self.arch()?;
+        let cc = self
+            .analysis_view(address)
+            .and_then(|view| CoreFunction::starting_at(&view, address))
+            .and_then(|f| f.calling_convention())
+            .or_else(|| self.calling_convention())?;
+        let sp_name = register_name(arch, unsafe {
+            sys::BNGetArchitectureStackPointerRegister(arch)
+        })?;
+        let link = register_name(arch, unsafe { sys::BNGetArchitectureLinkRegister(arch) });
+        let arg_registers: Vec<String> = cc
+            .integer_argument_registers()
+            .into_iter()
+            .filter_map(|reg| register_name(arch, reg))
+            .collect();
+
+        // stack layout at the callee's entry, from the new stack pointer up:
+        // return address (without a link register), shadow space, stack arguments
+        let slot = self.address_size() as u64;
+        let sp = self.sp();
+        let shadow = if cc.stack_reserved_for_argument_registers() {
+            arg_registers.len() as u64 * slot
+        } else {
+            0
+        };
+        let stack_args = &args[args.len().min(arg_registers.len())..];
+        let args_size = shadow + stack_args.len() as u64 * slot;
+        let args_base = (sp - RED_ZONE - args_size) & !(STACK_ALIGNMENT - 1);
+        let entry_sp = if link.is_some() {
+            args_base
+        } else {
+            args_base - slot
+        };
+
+        // the call runs on this thread, its registers are the ones restored
+        let thread = self.active_thread();
+        let saved_registers = self.registers();
+        let Some(saved_stack) = self.read_memory_uncached(entry_sp, (sp - entry_sp) as usize)
+        else {
+            log::warn!("cannot save the stack at 0x{:x}", entry_sp);
+            return None;
+        };
+
+        // scratch writes, restored below and kept out of the patch journal
+        let mut ok = true;
+        if link.is_none() {
+            ok &= self.write_memory_unjournaled(entry_sp, &self.pointer_bytes(trap));
+        }
+        for (i, &arg) in stack_args.iter().enumerate() {
+            let address = args_base + shadow + i as u64 * slot;
+            ok &= self.write_memory_unjournaled(address, &self.pointer_bytes(arg));
+        }
+        for (name, &arg) in arg_registers.iter().zip(args) {
+            ok &= self.set_reg_u64(name, arg);
+        }
+        if let Some(link) = &link {
+            ok &= self.set_reg_u64(link, trap);
+        }
+        ok &= self.set_reg_u64(&sp_name, entry_sp);
+        ok &= self.set_ip(address);
+
+        let result = if !ok {
+            log::warn!("failed to set up the call to 0x{:x}", address);
+            None
+        } else {
+            loop {
+                let reason = self.run_to_and_wait(&[trap]);
+                if reason == BNDebugStopReason::ProcessExited {
+                    return None;
+                }
+                if self.ip() != trap {
+                    log::warn!("call to 0x{:x} stopped at 0x{:x}", address, self.ip());
+                    break None;
+                }
+                // another thread reached the trap, keep following ours
+                if self.active_thread().tid != thread.tid {
+                    self.set_active_thread(&thread);
+                    continue;
+                }
+                // not our return, the trap location was reached deeper in the stack
+                if self.sp() < entry_sp {
+                    continue;
+                }
+                break cc
+                    .integer_return_register()
+                    .and_then(|reg| register_name(arch, reg))
+                    .and_then(|name| self.reg_u64(&name));
+            }
+        };
+
+        if self.active_thread().tid != thread.tid {
+            self.set_active_thread(&thread);
+        }
+        if !self.write_memory_unjournaled(entry_sp, &saved_stack) {
+            log::warn!("failed to restore the stack at 0x{:x}", entry_sp);
+        }
+        self.restore_registers(&saved_registers);
+        result
+    }
+
+    /// Write back every register of a saved register file
+    fn restore_registers(&self, saved: &[DebugRegister]) {
+        for reg in saved {
+            // the core reads a full 512-bit value
+            let mut buffer = [0u8; MAX_REGISTER_SIZE];
+            let len = reg.value.len().min(MAX_REGISTER_SIZE);
+            buffer[..len].copy_from_slice(&reg.value[..len]);
+            let name = CString::new(reg.name.as_str()).unwrap();
+            let ok = unsafe {
+                ffi::BNDebuggerSetRegisterValue(self.handle(), name.as_ptr(), buffer.as_ptr())
+            };
+            if !ok {
+                log::debug!("cannot restore {}", reg.name);
+            }
+        }
+        self.inner.cache.invalidate_registers();
+    }
+}
@@ -0,0 +1,689 @@
+// module-relative basic block coverage, in the drcov format of DynamoRIO, Frida and Lighthouse
+
+use crate::trace::{TraceEntry, TraceSink};
+use crate::types::{CoreFunction, CoreView};
+use crate::{BNDebugStopReason, DebugModule, DebuggerController};
+use binaryninja::binary_view::BinaryView;
+use binaryninjacore_sys as sys;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// A module blocks are relative to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverageModule {
+    pub path: String,
+    pub base: u64,
+    pub end: u64,
+    pub entry: u64,
+    pub checksum: u32,
+    pub timestamp: u32,
+}
+
+impl CoverageModule {
+    fn file_name(&self) -> Option<&str> {
+        // drcov files from Windows targets use backslashes
+        self.path.rsplit(['/', '\\']).next()
+    }
+}
+
+/// An executed basic block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CoveredBlock {
+    /// Index into `Coverage::modules`
+    pub module: u16,
+    /// Offset from the module base
+    pub offset: u32,
+    pub size: u16,
+}
+
+impl fmt::Display for CoveredBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "module[{}]+0x{:x} ({} bytes)",
+            self.module, self.offset, self.size
+        )
+    }
+}
+
+/// Set of executed basic blocks, relative to the modules containing them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    modules: Vec<CoverageModule>,
+    /// Size by (module, offset)
+    blocks: BTreeMap<(u16, u32), u16>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn modules(&self) -> &[CoverageModule] {
+        &self.modules
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = CoveredBlock> + '_ {
+        self.blocks
+            .iter()
+            .map(|(&(module, offset), &size)| CoveredBlock {
+                module,
+                offset,
+                size,
+            })
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Index of the module with this path and base, added if it is new
+    pub fn add_module(&mut self, path: &str, base: u64, end: u64) -> u16 {
+        if let Some(index) = self
+            .modules
+            .iter()
+            .position(|m| m.path == path && m.base == base)
+        {
+            let module = &mut self.modules[index];
+            module.end = module.end.max(end);
+            return index as u16;
+        }
+        self.modules.push(CoverageModule {
+            path: path.to_owned(),
+            base,
+            end,
+            entry: 0,
+            checksum: 0,
+            timestamp: 0,
+        });
+        (self.modules.len() - 1) as u16
+    }
+
+    /// Add a block, keeping the larger size if it is already covered
+    pub fn add_block(&mut self, module: u16, offset: u32, size: u16) {
+        let covered = self.blocks.entry((module, offset)).or_insert(size);
+        *covered = (*covered).max(size);
+    }
+
+    pub fn contains(&self, module: u16, offset: u32) -> bool {
+        self.blocks.contains_key(&(module, offset))
+    }
+
+    /// Add the blocks of another coverage, matching modules by path and base
+    pub fn merge(&mut self, other: &Coverage) {
+        let ids: Vec<u16> = other
+            .modules
+            .iter()
+            .map(|m| self.add_module(&m.path, m.base, m.end))
+            .collect();
+        for block in other.blocks() {
+            self.add_block(ids[block.module as usize], block.offset, block.size);
+        }
+    }
+
+    /// Blocks of the module whose file name matches the file behind `bv`, at
+    /// the view's addresses
+    pub fn blocks_in_view(&self, bv: &BinaryView) -> Vec<(u64, u16)> {
+        let Some(view) =
+            (unsafe { CoreView::from_raw(sys::BNNewViewReference(bv.handle as *mut _)) })
+        else {
+            return Vec::new();
+        };
+        let Some(path) = view.original_filename() else {
+            return Vec::new();
+        };
+        let file_name = Path::new(&path).file_name().and_then(|name| name.to_str());
+        let base = view.image_base();
+        self.blocks()
+            .filter(|block| {
+                file_name.is_some() && self.modules[block.module as usize].file_name() == file_name
+            })
+            .map(|block| (base + block.offset as u64, block.size))
+            .collect()
+    }
+
+    /// drcov version 2 with a binary block table, as written by DynamoRIO
+    pub fn write_drcov(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "DRCOV VERSION: 2")?;
+        writeln!(out, "DRCOV FLAVOR: binja-debugger")?;
+        writeln!(out, "Module Table: version 2, count {}", self.modules.len())?;
+        writeln!(
+            out,
+            "Columns: id, base, end, entry, checksum, timestamp, path"
+        )?;
+        for (id, module) in self.modules.iter().enumerate() {
+            writeln!(
+                out,
+                "{:3}, 0x{:016x}, 0x{:016x}, 0x{:016x}, 0x{:08x}, 0x{:08x}, {}",
+                id,
+                module.base,
+                module.end,
+                module.entry,
+                module.checksum,
+                module.timestamp,
+                module.path
+            )?;
+        }
+        writeln!(out, "BB Table: {} bbs", self.blocks.len())?;
+        for block in self.blocks() {
+            out.write_all(&block.offset.to_le_bytes())?;
+            out.write_all(&block.size.to_le_bytes())?;
+            out.write_all(&block.module.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn save_drcov(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_drcov(&mut out)?;
+        out.flush()
+    }
+
+    /// Parse drcov files of module table versions 1 to 4, with a binary or
+    /// text block table.
+    ///
+    /// Segments of the same module (v3 and later) are merged into one module
+    /// and their blocks made relative to its first segment.
+    pub fn parse_drcov(data: &[u8]) -> io::Result<Self> {
+        let mut pos = 0;
+        let mut line = || next_line(data, &mut pos);
+
+        let mut columns: Vec<String> = DEFAULT_COLUMNS.iter().map(|c| c.to_string()).collect();
+        let module_count = loop {
+            let text = line().ok_or_else(|| invalid("no module table"))?;
+            if let Some(table) = text.strip_prefix("Module Table:") {
+                // "version 2, count 5" or a bare count in version 1
+                let count = table.rsplit([' ', ',']).next().unwrap_or_default();
+                break count.trim().parse::<usize>().map_err(|_| invalid(text))?;
+            }
+        };
+        // the count comes from the file, rows are only added as they parse
+        let mut rows = Vec::new();
+        while rows.len() < module_count {
+            let text = line().ok_or_else(|| invalid("module table too short"))?;
+            if let Some(names) = text.strip_prefix("Columns:") {
+                columns = names.split(',').map(|c| c.trim().to_owned()).collect();
+                continue;
+            }
+            rows.push(ModuleRow::parse(text, &columns).ok_or_else(|| invalid(text))?);
+        }
+
+        let block_count = loop {
+            let text = line().ok_or_else(|| invalid("no block table"))?;
+            if let Some(table) = text.strip_prefix("BB Table:") {
+                let count = table.split_whitespace().next().unwrap_or_default();
+                break count.parse::<usize>().map_err(|_| invalid(text))?;
+            }
+        };
+
+        // one module per containing module, rows by their id
+        let mut coverage = Coverage::new();
+        let mut containers: BTreeMap<u64, (u16, u64)> = BTreeMap::new();
+        for row in &rows {
+            let container = rows
+                .iter()
+                .find(|r| r.id == row.containing_id)
+                .unwrap_or(row);
+            let id = coverage.add_module(&container.path, container.start, row.end);
+            if row.id == container.id {
+                let module = &mut coverage.modules[id as usize];
+                module.entry = row.entry;
+                module.checksum = row.checksum;
+                module.timestamp = row.timestamp;
+            }
+            let segment = row
+                .start
+                .checked_sub(container.start)
+                .ok_or_else(|| invalid("segment before its module"))?;
+            containers.insert(row.id, (id, segment));
+        }
+        let mut add = |module: u64, offset: u64, size: u64| -> io::Result<()> {
+            let &(id, segment) = containers
+                .get(&module)
+                .ok_or_else(|| invalid("block of an unknown module"))?;
+            let offset = segment
+                .checked_add(offset)
+                .and_then(|offset| u32::try_from(offset).ok())
+                .ok_or_else(|| invalid("block offset"))?;
+            coverage.add_block(id, offset, size.min(u16::MAX as u64) as u16);
+            Ok(())
+        };
+
+        let table = &data[pos..];
+        if table.starts_with(b"module[") {
+            // drcov -dump_text: "module[  2]: 0x0000000000001234,   7"
+            let mut pos = 0;
+            for _ in 0..block_count {
+                let text =
+                    next_line(table, &mut pos).ok_or_else(|| invalid("block table too short"))?;
+                let parsed = (|| {
+                    let (module, rest) = text.strip_prefix("module[")?.split_once("]:")?;
+                    let (offset, size) = rest.split_once(',')?;
+                    Some((
+                        module.trim().parse().ok()?,
+                        parse_number(offset)?,
+                        parse_number(size)?,
+                    ))
+                })();
+                let (module, offset, size) = parsed.ok_or_else(|| invalid(text))?;
+                add(module, offset, size)?;
+            }
+        } else {
+            if block_count
+                .checked_mul(8)
+                .is_none_or(|size| table.len() < size)
+            {
+                return Err(invalid("block table too short"));
+            }
+            for entry in table.chunks_exact(8).take(block_count) {
+                let offset = u32::from_le_bytes(entry[..4].try_into().unwrap());
+                let size = u16::from_le_bytes(entry[4..6].try_into().unwrap());
+                let module = u16::from_le_bytes(entry[6..].try_into().unwrap());
+                add(module as u64, offset as u64, size as u64)?;
+            }
+        }
+        Ok(coverage)
+    }
+
+    pub fn load_drcov(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse_drcov(&std::fs::read(path)?)
+    }
+
+    /// Blocks executed by a recorded trace, see `CoverageRecorder`
+    pub fn from_trace<'a>(
+        dbg: &DebuggerController,
+        entries: impl IntoIterator<Item = &'a TraceEntry>,
+    ) -> Self {
+        let mut recorder = dbg.coverage_recorder();
+        for entry in entries {
+            recorder.add_address(entry.pc);
+        }
+        recorder.into_coverage()
+    }
+}
+
+/// Module table columns of version 1 files, which have no "Columns:" line
+const DEFAULT_COLUMNS: [&str; 5] = ["id", "base", "end", "entry", "path"];
+
+struct ModuleRow {
+    id: u64,
+    containing_id: u64,
+    start: u64,
+    end: u64,
+    entry: u64,
+    checksum: u32,
+    timestamp: u32,
+    path: String,
+}
+
+impl ModuleRow {
+    fn parse(text: &str, columns: &[String]) -> Option<Self> {
+        // the path is the last column and may contain commas
+        let values: Vec<&str> = text.splitn(columns.len(), ',').map(str::trim).collect();
+        let value = |names: &[&str]| {
+            let index = columns.iter().position(|c| names.contains(&c.as_str()))?;
+            values.get(index).copied()
+        };
+        let number = |names: &[&str]| value(names).and_then(parse_number);
+        let id = number(&["id"])?;
+        Some(Self {
+            id,
+            containing_id: number(&["containing_id"]).unwrap_or(id),
+            start: number(&["base", "start"])?,
+            end: number(&["end"])?,
+            entry: number(&["entry"]).unwrap_or_default(),
+            checksum: number(&["checksum"]).unwrap_or_default() as u32,
+            timestamp: number(&["timestamp"]).unwrap_or_default() as u32,
+            path: value(&["path"])?.to_owned(),
+        })
+    }
+}
+
+fn parse_number(text: &str) -> Option<u64> {
+    let text = text.trim();
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Next `\n` terminated line as text, without the line ending
+fn next_line<'a>(data: &'a [u8], pos: &mut usize) -> Option<&'a str> {
+    let rest = data.get(*pos..).filter(|rest| !rest.is_empty())?;
+    let len = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+    *pos += (len + 1).min(rest.len());
+    std::str::from_utf8(&rest[..len])
+        .ok()
+        .map(|line| line.trim_end_matches('\r'))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid drcov file: {}", message),
+    )
+}
+
+/// Collects the blocks containing executed addresses, made module-relative
+/// by the debugger. As a `TraceSink` it covers every recorded entry, which is
+/// cheapest with `TraceMode::BasicBlock`.
+pub struct CoverageRecorder<'a> {
+    dbg: &'a DebuggerController,
+    coverage: Coverage,
+    modules: Vec<DebugModule>,
+    /// Addresses already added
+    seen: HashSet<u64>,
+}
+
+impl CoverageRecorder<'_> {
+    /// Cover the basic block of the analysis containing `address`, or the
+    /// instruction at it if there is no analysis. Addresses outside every
+    /// module are ignored.
+    pub fn add_address(&mut self, address: u64) {
+        if !self.seen.insert(address) {
+            return;
+        }
+        let Some((name, offset)) = self.dbg.absolute_to_relative(address) else {
+            return;
+        };
+        let find = |modules: &[DebugModule]| {
+            modules
+                .iter()
+                .find(|m| m.name == name || m.short_name == name)
+                .cloned()
+        };
+        let module = match find(&self.modules) {
+            Some(module) => module,
+            None => {
+                // loaded since the last lookup
+                self.modules = self.dbg.modules();
+                let Some(module) = find(&self.modules) else {
+                    return;
+                };
+                module
+            }
+        };
+        let (start, size) = self.block_at(address).unwrap_or((address, 1));
+        let start = start.max(module.address);
+        let id = self.coverage.add_module(
+            &module.name,
+            module.address,
+            module.address + module.size as u64,
+        );
+        let Ok(offset) = u32::try_from(offset - (address - start)) else {
+            return;
+        };
+        self.coverage
+            .add_block(id, offset, size.min(u16::MAX as u64) as u16);
+    }
+
+    fn block_at(&self, address: u64) -> Option<(u64, u64)> {
+        let view = self.dbg.analysis_view(address)?;
+        let func = CoreFunction::at(&view, address)?;
+        unsafe {
+            let block = sys::BNGetFunctionBasicBlockAtAddress(func.handle(), func.arch(), address);
+            if block.is_null() {
+                return None;
+            }
+            let start = sys::BNGetBasicBlockStart(block);
+            let end = sys::BNGetBasicBlockEnd(block);
+            sys::BNFreeBasicBlock(block);
+            Some((start, end - start))
+        }
+    }
+
+    pub fn coverage(&self) -> &Coverage {
+        &self.coverage
+    }
+
+    pub fn into_coverage(self) -> Coverage {
+        self.coverage
+    }
+}
+
+impl TraceSink for CoverageRecorder<'_> {
+    fn record(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        self.add_address(entry.pc);
+        Ok(())
+    }
+}
+
+impl DebuggerController {
+    /// Coverage collector, feed it with `add_address` or pass it to
+    /// `TraceRecorder::record`
+    pub fn coverage_recorder(&self) -> CoverageRecorder<'_> {
+        CoverageRecorder {
+            dbg: self,
+            coverage: Coverage::new(),
+            modules: self.modules(),
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Run the target with a one-shot breakpoint on every basic block start
+    /// of the functions starting at `functions`, or of all functions of the
+    /// view if empty, and return the blocks that executed.
+    ///
+    /// Each breakpoint is removed on its first hit, so hot blocks cost a
+    /// single stop. Collection ends when the target stops for any other
+    /// reason, including a breakpoint of the user's at a block start; the
+    /// remaining breakpoints are then removed. Covered blocks are
+    /// highlighted in the view.
+    pub fn collect_block_coverage(&self, functions: &[u64]) -> Coverage {
+        let mut recorder = self.coverage_recorder();
+        let Some(view) = self.live_view() else {
+            log::warn!("no view to collect block coverage from");
+            return recorder.into_coverage();
+        };
+        let mut starts = HashSet::new();
+        for_each_block(&view, functions, |block| {
+            starts.insert(unsafe { sys::BNGetBasicBlockStart(block) });
+        });
+
+        // blocks with a breakpoint of the user's keep it, hitting it ends the collection
+        let mut pending: HashSet<u64> = starts
+            .iter()
+            .copied()
+            .filter(|&address| !self.contains_breakpoint(address))
+            .collect();
+        for (i, &address) in pending.iter().enumerate() {
+            self.add_breakpoint(address);
+            if (i + 1) % BREAKPOINT_REPORT_INTERVAL == 0 || i + 1 == pending.len() {
+                log::info!("{}/{} coverage breakpoints added", i + 1, pending.len());
+            }
+        }
+
+        let reason = loop {
+            let reason = self.go_and_wait();
+            if reason != BNDebugStopReason::Breakpoint {
+                break reason;
+            }
+            let pc = self.ip();
+            if !starts.contains(&pc) {
+                break reason;
+            }
+            recorder.add_address(pc);
+            if !pending.remove(&pc) {
+                break reason;
+            }
+            self.delete_breakpoint(pc);
+        };
+        for address in pending {
+            self.delete_breakpoint(address);
+        }
+
+        let coverage = recorder.into_coverage();
+        let covered: HashSet<u64> = coverage
+            .blocks()
+            .map(|block| coverage.modules[block.module as usize].base + block.offset as u64)
+            .collect();
+        for_each_block(&view, functions, |block| unsafe {
+            if covered.contains(&sys::BNGetBasicBlockStart(block)) {
+                sys::BNSetUserBasicBlockHighlight(block, COVERED_HIGHLIGHT);
+            }
+        });
+        log::info!(
+            "{} of {} blocks covered, {}",
+            covered.len(),
+            starts.len(),
+            crate::stop_reason_string(reason)
+        );
+        coverage
+    }
+}
+
+/// Breakpoints added between progress reports, each is added on its own
+const BREAKPOINT_REPORT_INTERVAL: usize = 10000;
+
+const COVERED_HIGHLIGHT: sys::BNHighlightColor = sys::BNHighlightColor {
+    style: sys::BNHighlightColorStyle::StandardHighlightColor,
+    color: sys::BNHighlightStandardColor::GreenHighlightColor,
+    mixColor: sys::BNHighlightStandardColor::NoHighlightColor,
+    mix: 0,
+    r: 0,
+    g: 0,
+    b: 0,
+    alpha: 255,
+};
+
+/// Visit the basic blocks of the functions starting at `functions`, or of
+/// every function of the view if empty
+fn for_each_block(
+    view: &CoreView,
+    functions: &[u64],
+    mut visit: impl FnMut(*mut sys::BNBasicBlock),
+) {
+    let funcs: Vec<CoreFunction> = if functions.is_empty() {
+        unsafe {
+            let mut count = 0usize;
+            let list = sys::BNGetAnalysisFunctionList(view.handle(), &mut count);
+            if list.is_null() {
+                return;
+            }
+            let funcs = std::slice::from_raw_parts(list, count)
+                .iter()
+                .filter_map(|&func| CoreFunction::from_borrowed(func))
+                .collect();
+            sys::BNFreeFunctionList(list, count);
+            funcs
+        }
+    } else {
+        functions
+            .iter()
+            .filter_map(|&address| CoreFunction::starting_at(view, address))
+            .collect()
+    };
+    for func in funcs {
+        unsafe {
+            let mut count = 0usize;
+            let blocks = sys::BNGetFunctionBasicBlockList(func.handle(), &mut count);
+            if blocks.is_null() {
+                continue;
+            }
+            for &block in std::slice::from_raw_parts(blocks, count) {
+                visit(block);
+            }
+            sys::BNFreeBasicBlockList(blocks, count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drcov(header: &str, blocks: &[(u32, u16, u16)]) -> Vec<u8> {
+        let mut data = header.as_bytes().to_vec();
+        for &(offset, size, module) in blocks {
+            data.extend_from_slice(&offset.to_le_bytes());
+            data.extend_from_slice(&size.to_le_bytes());
+            data.extend_from_slice(&module.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_drcov_roundtrip() {
+        let mut coverage = Coverage::new();
+        let main = coverage.add_module("/tmp/a, b/main", 0x5555_5555_4000, 0x5555_5555_9000);
+        let libc = coverage.add_module("/usr/lib/libc.so.6", 0x7fff_f7d8_0000, 0x7fff_f7fa_0000);
+        coverage.add_block(main, 0x1130, 12);
+        coverage.add_block(main, 0x1130, 4);
+        coverage.add_block(libc, 0x2_8000, 300);
+        coverage.add_block(main, 0x10, 2);
+
+        let mut out = Vec::new();
+        coverage.write_drcov(&mut out).unwrap();
+        let parsed = Coverage::parse_drcov(&out).unwrap();
+        assert_eq!(parsed, coverage);
+        assert_eq!(parsed.len(), 3);
+        assert!(parsed.contains(main, 0x1130));
+        assert_eq!(parsed.blocks().next().unwrap().size, 2);
+        assert_eq!(parsed.modules()[main as usize].path, "/tmp/a, b/main");
+    }
+
+    #[test]
+    fn test_drcov_versions() {
+        let v1 = drcov(
+            "DRCOV VERSION: 1\nDRCOV FLAVOR: drcov\nModule Table: 1\n 0, 0x400000, 0x401000, 0x400100, /bin/true\nBB Table: 1 bbs\n",
+            &[(0x100, 5, 0)],
+        );
+        let v1 = Coverage::parse_drcov(&v1).unwrap();
+        assert_eq!(v1.modules()[0].path, "/bin/true");
+        assert_eq!(v1.modules()[0].entry, 0x400100);
+        assert!(v1.contains(0, 0x100));
+
+        // Frida's drcov output, CRLF line endings
+        let v2 = drcov(
+            "DRCOV VERSION: 2\r\nDRCOV FLAVOR: frida\r\nModule Table: version 2, count 2\r\nColumns: id, base, end, entry, checksum, timestamp, path\r\n0, 0x7f0000000000, 0x7f0000010000, 0x0, 0x0, 0x0, /lib/ld.so\r\n1, 0x555555554000, 0x555555558000, 0x0, 0x0, 0x0, C:\\bin\\app.exe\r\nBB Table: 2 bbs\r\n",
+            &[(0x20, 3, 1), (0x40, 8, 0)],
+        );
+        let v2 = Coverage::parse_drcov(&v2).unwrap();
+        assert_eq!(v2.modules()[1].file_name(), Some("app.exe"));
+        assert!(v2.contains(1, 0x20) && v2.contains(0, 0x40));
+
+        // segments 1 and 2 belong to module 1, offsets are per segment
+        let v4 = drcov(
+            "DRCOV VERSION: 2\nDRCOV FLAVOR: drcov-64\nModule Table: version 4, count 3\nColumns: id, containing_id, start, end, entry, offset, checksum, timestamp, path\n  0,   0, 0x0000000000400000, 0x0000000000402000, 0x0000000000400500, 0000000000000000, 0x00000000, 0x00000000, /bin/app\n  1,   1, 0x00007f0000000000, 0x00007f0000001000, 0x0000000000000000, 0000000000000000, 0x00000000, 0x00000000, /lib/libc.so\n  2,   1, 0x00007f0000003000, 0x00007f0000004000, 0x0000000000000000, 0000000000003000, 0x00000000, 0x00000000, /lib/libc.so\nBB Table: 3 bbs\n",
+            &[(0x10, 4, 0), (0x20, 4, 1), (0x30, 6, 2)],
+        );
+        let v4 = Coverage::parse_drcov(&v4).unwrap();
+        assert_eq!(v4.modules().len(), 2);
+        assert_eq!(v4.modules()[1].end, 0x7f00_0000_4000);
+        assert!(v4.contains(1, 0x20) && v4.contains(1, 0x3030));
+
+        let text = b"DRCOV VERSION: 2\nDRCOV FLAVOR: drcov\nModule Table: version 2, count 1\nColumns: id, base, end, entry, checksum, timestamp, path\n0, 0x1000, 0x2000, 0x0, 0x0, 0x0, /a\nBB Table: 2 bbs\nmodule[  0]: 0x0000000000000010,   7\nmodule[  0]: 0x0000000000000400,  12\n";
+        let text = Coverage::parse_drcov(text).unwrap();
+        assert_eq!(text.len(), 2);
+        assert!(text.contains(0, 0x400));
+
+        let truncated = drcov(
+            "DRCOV VERSION: 2\nModule Table: version 2, count 1\nColumns: id, base, end, entry, checksum, timestamp, path\n0, 0x1000, 0x2000, 0x0, 0x0, 0x0, /a\nBB Table: 2 bbs\n",
+            &[(0x10, 1, 0)],
+        );
+        assert!(Coverage::parse_drcov(&truncated).is_err());
+
+        // segment 1 starts before the module containing it
+        let malformed = drcov(
+            "DRCOV VERSION: 2\nModule Table: version 4, count 2\nColumns: id, containing_id, start, end, entry, offset, checksum, timestamp, path\n  0,   0, 0x0000000000400000, 0x0000000000402000, 0x0, 0x0, 0x0, 0x0, /bin/app\n  1,   0, 0x0000000000300000, 0x0000000000301000, 0x0, 0x0, 0x0, 0x0, /bin/app\nBB Table: 1 bbs\n",
+            &[(0x10, 4, 1)],
+        );
+        assert!(Coverage::parse_drcov(&malformed).is_err());
+
+        // counts too large for the file
+        let huge_modules = b"DRCOV VERSION: 2\nModule Table: version 2, count 18446744073709551615\nColumns: id, base, end, entry, checksum, timestamp, path\n0, 0x1000, 0x2000, 0x0, 0x0, 0x0, /a\n";
+        assert!(Coverage::parse_drcov(huge_modules).is_err());
+        let huge_blocks = drcov(
+            "DRCOV VERSION: 2\nModule Table: version 2, count 1\nColumns: id, base, end, entry, checksum, timestamp, path\n0, 0x1000, 0x2000, 0x0, 0x0, 0x0, /a\nBB Table: 2305843009213693952 bbs\n",
+            &[(0x10, 1, 0)],
+        );
+        assert!(Coverage::parse_drcov(&huge_blocks).is_err());
+    }
+}
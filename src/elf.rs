@@ -0,0 +1,483 @@
+// minimal little-endian ELF parsing for modules of the target, from memory or disk
+
+use crate::memory::PAGE_SIZE;
+use crate::DebuggerController;
+use std::io::{Read, Seek, SeekFrom};
+
+pub(crate) const PT_LOAD: u32 = 1;
+pub(crate) const PT_DYNAMIC: u32 = 2;
+pub(crate) const PT_NOTE: u32 = 4;
+pub(crate) const PT_GNU_EH_FRAME: u32 = 0x6474_e550;
+
+pub(crate) const DT_NULL: u64 = 0;
+pub(crate) const DT_STRTAB: u64 = 5;
+pub(crate) const DT_SONAME: u64 = 14;
+pub(crate) const DT_DEBUG: u64 = 21;
+
+pub(crate) const PF_X: u32 = 1;
+pub(crate) const PF_W: u32 = 2;
+pub(crate) const PF_R: u32 = 4;
+
+const NT_GNU_BUILD_ID: u32 = 3;
+/// Largest note section or segment read from a file on disk
+const MAX_NOTES_SIZE: usize = 64 * 1024;
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOTE: u32 = 7;
+const SHT_NOBITS: u32 = 8;
+const SHT_DYNSYM: u32 = 11;
+const SHN_UNDEF: u16 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const STT_GNU_IFUNC: u8 = 10;
+
+pub(crate) fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+pub(crate) fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+pub(crate) fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// Address sized value, 4 or 8 bytes
+fn addr_at(data: &[u8], offset: usize, is_64: bool) -> Option<u64> {
+    if is_64 {
+        u64_at(data, offset)
+    } else {
+        u32_at(data, offset).map(u64::from)
+    }
+}
+
+/// (name offset, type, address, file offset, size, link)
+type SectionHeader = (u32, u32, u64, u64, u64, u32);
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ElfHeader {
+    pub is_64: bool,
+    pub elf_type: u16,
+    pub machine: u16,
+    pub entry: u64,
+    pub phoff: u64,
+    pub phentsize: u16,
+    pub phnum: u16,
+    pub shoff: u64,
+    pub shentsize: u16,
+    pub shnum: u16,
+    pub shstrndx: u16,
+}
+
+impl ElfHeader {
+    /// Size of the header of a 64-bit ELF, enough to parse either class
+    pub const MAX_SIZE: usize = 64;
+
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.get(..4)? != b"\x7fELF" {
+            return None;
+        }
+        let is_64 = match data.get(4)? {
+            1 => false,
+            2 => true,
+            _ => return None,
+        };
+        // big-endian targets are not supported
+        if *data.get(5)? != 1 {
+            return None;
+        }
+        // entry, phoff and shoff are address sized, the rest follows e_flags
+        let a = if is_64 { 8 } else { 4 };
+        let phoff_at = 24 + a;
+        let shoff_at = phoff_at + a;
+        let flags_at = shoff_at + a;
+        Some(Self {
+            is_64,
+            elf_type: u16_at(data, 16)?,
+            machine: u16_at(data, 18)?,
+            entry: addr_at(data, 24, is_64)?,
+            phoff: addr_at(data, phoff_at, is_64)?,
+            shoff: addr_at(data, shoff_at, is_64)?,
+            phentsize: u16_at(data, flags_at + 6)?,
+            phnum: u16_at(data, flags_at + 8)?,
+            shentsize: u16_at(data, flags_at + 10)?,
+            shnum: u16_at(data, flags_at + 12)?,
+            shstrndx: u16_at(data, flags_at + 14)?,
+        })
+    }
+
+    /// Size of the program header table
+    pub fn program_headers_size(&self) -> usize {
+        self.phentsize as usize * self.phnum as usize
+    }
+
+    /// Parse the program header table, `table` starts at `phoff`
+    pub fn program_headers(&self, table: &[u8]) -> Vec<ProgramHeader> {
+        (0..self.phnum as usize)
+            .filter_map(|i| ProgramHeader::parse(table, i * self.phentsize as usize, self.is_64))
+            .collect()
+    }
+
+    /// Size of the section header table
+    pub fn section_headers_size(&self) -> usize {
+        self.shentsize as usize * self.shnum as usize
+    }
+
+    /// Section header at `at` of `table`
+    fn section_header(&self, table: &[u8], at: usize) -> Option<SectionHeader> {
+        // `at` comes from the file, offsets past it must not overflow
+        let entry = table.get(at..)?;
+        let name = u32_at(entry, 0)?;
+        let kind = u32_at(entry, 4)?;
+        let (address, offset, size, link) = if self.is_64 {
+            (
+                u64_at(entry, 16)?,
+                u64_at(entry, 24)?,
+                u64_at(entry, 32)?,
+                u32_at(entry, 40)?,
+            )
+        } else {
+            (
+                u32_at(entry, 12)? as u64,
+                u32_at(entry, 16)? as u64,
+                u32_at(entry, 20)? as u64,
+                u32_at(entry, 24)?,
+            )
+        };
+        Some((name, kind, address, offset, size, link))
+    }
+
+    /// Sections of a complete file image, `.bss` like sections have no data
+    pub fn sections<'a>(&self, file: &'a [u8]) -> Vec<Section<'a>> {
+        let headers: Vec<SectionHeader> = (0..self.shnum as usize)
+            .filter_map(|i| {
+                let at = (self.shoff as usize).checked_add(i * self.shentsize as usize)?;
+                self.section_header(file, at)
+            })
+            .collect();
+        let strtab = headers
+            .get(self.shstrndx as usize)
+            .and_then(|&(_, _, _, offset, size, _)| {
+                file.get(offset as usize..offset.checked_add(size)? as usize)
+            })
+            .unwrap_or_default();
+
+        headers
+            .iter()
+            .map(|&(name, kind, address, offset, size, link)| {
+                let data = if kind == SHT_NOBITS {
+                    &[][..]
+                } else {
+                    file.get(offset as usize..offset.saturating_add(size) as usize)
+                        .unwrap_or_default()
+                };
+                Section {
+                    name: c_string_at(strtab, name as usize),
+                    kind,
+                    address,
+                    link,
+                    data,
+                }
+            })
+            .collect()
+    }
+
+    /// Defined function and data symbols of `.symtab` and `.dynsym`, at link-time addresses
+    pub fn symbols(&self, file: &[u8]) -> Vec<ElfSymbol> {
+        let sections = self.sections(file);
+        let entry_size = if self.is_64 { 24 } else { 16 };
+        let mut symbols = Vec::new();
+        for table in sections
+            .iter()
+            .filter(|s| s.kind == SHT_SYMTAB || s.kind == SHT_DYNSYM)
+        {
+            let Some(strtab) = sections.get(table.link as usize) else {
+                continue;
+            };
+            for entry in table.data.chunks_exact(entry_size) {
+                let parsed = if self.is_64 {
+                    (|| {
+                        Some((
+                            u32_at(entry, 0)?,
+                            entry[4],
+                            u16_at(entry, 6)?,
+                            u64_at(entry, 8)?,
+                        ))
+                    })()
+                } else {
+                    (|| {
+                        Some((
+                            u32_at(entry, 0)?,
+                            entry[12],
+                            u16_at(entry, 14)?,
+                            u32_at(entry, 4)? as u64,
+                        ))
+                    })()
+                };
+                let Some((name, info, section, address)) = parsed else {
+                    continue;
+                };
+                let kind = info & 0xf;
+                if !matches!(kind, STT_OBJECT | STT_FUNC | STT_GNU_IFUNC)
+                    || section == SHN_UNDEF
+                    || address == 0
+                {
+                    continue;
+                }
+                let name = c_string_at(strtab.data, name as usize);
+                if name.is_empty() {
+                    continue;
+                }
+                symbols.push(ElfSymbol {
+                    name,
+                    address,
+                    is_function: kind != STT_OBJECT,
+                });
+            }
+        }
+        symbols
+    }
+}
+
+/// GNU build-id of a file on disk, from its note sections or, for files
+/// without section headers, its PT_NOTE segments. Only the headers and
+/// notes are read, each capped at `MAX_NOTES_SIZE`.
+pub(crate) fn file_build_id(file: &mut (impl Read + Seek)) -> Option<Vec<u8>> {
+    let header = ElfHeader::parse(&read_at(file, 0, ElfHeader::MAX_SIZE)?)?;
+    let note_at = |file: &mut _, offset: u64, size: u64| {
+        let data = read_at(file, offset, size.min(MAX_NOTES_SIZE as u64) as usize)?;
+        gnu_build_id(&data)
+    };
+
+    if header.shnum > 0 {
+        // a truncated section header table falls back to the segments
+        let table = read_at(file, header.shoff, header.section_headers_size()).unwrap_or_default();
+        let notes: Vec<(u64, u64)> = (0..header.shnum as usize)
+            .filter_map(|i| header.section_header(&table, i * header.shentsize as usize))
+            .filter(|&(_, kind, ..)| kind == SHT_NOTE)
+            .map(|(_, _, _, offset, size, _)| (offset, size))
+            .collect();
+        if let Some(id) = notes
+            .into_iter()
+            .find_map(|(offset, size)| note_at(file, offset, size))
+        {
+            return Some(id);
+        }
+    }
+    let table = read_at(file, header.phoff, header.program_headers_size())?;
+    header
+        .program_headers(&table)
+        .iter()
+        .filter(|ph| ph.p_type == PT_NOTE)
+        .find_map(|ph| note_at(file, ph.offset, ph.filesz))
+}
+
+/// `len` bytes at `offset`, `None` when the file is shorter
+fn read_at(file: &mut (impl Read + Seek), offset: u64, len: usize) -> Option<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let mut data = vec![0; len];
+    file.read_exact(&mut data).ok()?;
+    Some(data)
+}
+
+/// Descriptor of the NT_GNU_BUILD_ID note in a PT_NOTE segment or note section
+pub(crate) fn gnu_build_id(notes: &[u8]) -> Option<Vec<u8>> {
+    let align = |n: usize| (n + 3) & !3;
+    let mut at = 0;
+    while at + 12 <= notes.len() {
+        let name_size = u32_at(notes, at)? as usize;
+        let desc_size = u32_at(notes, at + 4)? as usize;
+        let kind = u32_at(notes, at + 8)?;
+        let name_at = at + 12;
+        let desc_at = name_at.checked_add(align(name_size))?;
+        let end = desc_at.checked_add(align(desc_size))?;
+        if kind == NT_GNU_BUILD_ID && notes.get(name_at..name_at + name_size)? == b"GNU\0" {
+            return Some(notes.get(desc_at..desc_at + desc_size)?.to_vec());
+        }
+        at = end;
+    }
+    None
+}
+
+fn c_string_at(table: &[u8], offset: usize) -> String {
+    table
+        .get(offset..)
+        .and_then(|s| s.split(|&b| b == 0).next())
+        .map(|s| String::from_utf8_lossy(s).into_owned())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ElfSymbol {
+    pub name: String,
+    pub address: u64,
+    pub is_function: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ProgramHeader {
+    pub p_type: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+impl ProgramHeader {
+    fn parse(table: &[u8], at: usize, is_64: bool) -> Option<Self> {
+        if is_64 {
+            Some(Self {
+                p_type: u32_at(table, at)?,
+                flags: u32_at(table, at + 4)?,
+                offset: u64_at(table, at + 8)?,
+                vaddr: u64_at(table, at + 16)?,
+                filesz: u64_at(table, at + 32)?,
+                memsz: u64_at(table, at + 40)?,
+                align: u64_at(table, at + 48)?,
+            })
+        } else {
+            Some(Self {
+                p_type: u32_at(table, at)?,
+                offset: u32_at(table, at + 4)? as u64,
+                vaddr: u32_at(table, at + 8)? as u64,
+                filesz: u32_at(table, at + 16)? as u64,
+                memsz: u32_at(table, at + 20)? as u64,
+                flags: u32_at(table, at + 24)?,
+                align: u32_at(table, at + 28)? as u64,
+            })
+        }
+    }
+
+    pub fn contains(&self, vaddr: u64) -> bool {
+        vaddr >= self.vaddr && vaddr - self.vaddr < self.memsz
+    }
+}
+
+/// Lowest PT_LOAD page, the link-time address the module base corresponds to
+pub(crate) fn link_base(headers: &[ProgramHeader]) -> Option<u64> {
+    headers
+        .iter()
+        .filter(|ph| ph.p_type == PT_LOAD)
+        .map(|ph| ph.vaddr / PAGE_SIZE * PAGE_SIZE)
+        .min()
+}
+
+pub(crate) struct Section<'a> {
+    pub name: String,
+    pub kind: u32,
+    pub address: u64,
+    /// Section index of the associated string table for symbol tables
+    pub link: u32,
+    pub data: &'a [u8],
+}
+
+/// `(d_tag, d_val)` pairs of a PT_DYNAMIC segment, up to DT_NULL
+pub(crate) fn dynamic_entries(data: &[u8], is_64: bool) -> Vec<(u64, u64)> {
+    let size = if is_64 { 8 } else { 4 };
+    data.chunks_exact(2 * size)
+        .map_while(|entry| {
+            let tag = addr_at(entry, 0, is_64)?;
+            let value = addr_at(entry, size, is_64)?;
+            (tag != DT_NULL).then_some((tag, value))
+        })
+        .collect()
+}
+
+impl DebuggerController {
+    /// ELF header and program headers of a module mapped at `base`
+    pub(crate) fn elf_headers(&self, base: u64) -> Option<(ElfHeader, Vec<ProgramHeader>)> {
+        let header = ElfHeader::parse(&self.read_memory(base, ElfHeader::MAX_SIZE)?)?;
+        let table = self.read_memory(
+            base.wrapping_add(header.phoff),
+            header.program_headers_size(),
+        )?;
+        let headers = header.program_headers(&table);
+        Some((header, headers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gnu_build_id() {
+        let mut notes = Vec::new();
+        // NT_GNU_PROPERTY_TYPE_0 note before the build-id
+        notes.extend_from_slice(&4u32.to_le_bytes());
+        notes.extend_from_slice(&8u32.to_le_bytes());
+        notes.extend_from_slice(&5u32.to_le_bytes());
+        notes.extend_from_slice(b"GNU\0");
+        notes.extend_from_slice(&[0; 8]);
+        // build-id with a descriptor that needs padding
+        notes.extend_from_slice(&4u32.to_le_bytes());
+        notes.extend_from_slice(&6u32.to_le_bytes());
+        notes.extend_from_slice(&NT_GNU_BUILD_ID.to_le_bytes());
+        notes.extend_from_slice(b"GNU\0");
+        notes.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef, 0x01, 0x02, 0, 0]);
+
+        assert_eq!(
+            gnu_build_id(&notes),
+            Some(vec![0xde, 0xad, 0xbe, 0xef, 0x01, 0x02])
+        );
+        assert_eq!(gnu_build_id(&notes[..28]), None);
+    }
+
+    #[test]
+    fn test_file_build_id() {
+        let mut notes = Vec::new();
+        notes.extend_from_slice(&4u32.to_le_bytes());
+        notes.extend_from_slice(&4u32.to_le_bytes());
+        notes.extend_from_slice(&NT_GNU_BUILD_ID.to_le_bytes());
+        notes.extend_from_slice(b"GNU\0");
+        notes.extend_from_slice(&[1, 2, 3, 4]);
+        let tables_at = 64 + notes.len() as u64;
+
+        let mut header = vec![0u8; 64];
+        header[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        let elf = |phnum: u16, shnum: u16, table: Vec<u8>| {
+            let mut file = header.clone();
+            file[32..40].copy_from_slice(&tables_at.to_le_bytes());
+            file[40..48].copy_from_slice(&tables_at.to_le_bytes());
+            file[54..56].copy_from_slice(&56u16.to_le_bytes());
+            file[56..58].copy_from_slice(&phnum.to_le_bytes());
+            file[58..60].copy_from_slice(&64u16.to_le_bytes());
+            file[60..62].copy_from_slice(&shnum.to_le_bytes());
+            file.extend_from_slice(&notes);
+            file.extend_from_slice(&table);
+            std::io::Cursor::new(file)
+        };
+
+        // a null section then the note section
+        let mut sections = vec![0u8; 128];
+        sections[64 + 4..64 + 8].copy_from_slice(&SHT_NOTE.to_le_bytes());
+        sections[64 + 24..64 + 32].copy_from_slice(&64u64.to_le_bytes());
+        sections[64 + 32..64 + 40].copy_from_slice(&(notes.len() as u64).to_le_bytes());
+        assert_eq!(
+            file_build_id(&mut elf(0, 2, sections)),
+            Some(vec![1, 2, 3, 4])
+        );
+
+        // no section headers, only a PT_NOTE segment
+        let mut segments = vec![0u8; 56];
+        segments[..4].copy_from_slice(&PT_NOTE.to_le_bytes());
+        segments[8..16].copy_from_slice(&64u64.to_le_bytes());
+        segments[32..40].copy_from_slice(&(notes.len() as u64).to_le_bytes());
+        assert_eq!(
+            file_build_id(&mut elf(1, 0, segments)),
+            Some(vec![1, 2, 3, 4])
+        );
+
+        // section header table past the end of the file
+        assert_eq!(file_build_id(&mut elf(0, 2, Vec::new())), None);
+    }
+}
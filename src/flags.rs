@@ -0,0 +1,211 @@
+// named access to the condition/status flags register
+
+use crate::DebuggerController;
+use std::fmt;
+
+const X86_FLAGS: &[(&str, u32)] = &[
+    ("CF", 0),
+    ("PF", 2),
+    ("AF", 4),
+    ("ZF", 6),
+    ("SF", 7),
+    ("TF", 8),
+    ("IF", 9),
+    ("DF", 10),
+    ("OF", 11),
+    ("NT", 14),
+    ("RF", 16),
+    ("VM", 17),
+    ("AC", 18),
+    ("VIF", 19),
+    ("VIP", 20),
+    ("ID", 21),
+];
+
+const ARM_FLAGS: &[(&str, u32)] = &[
+    ("N", 31),
+    ("Z", 30),
+    ("C", 29),
+    ("V", 28),
+    ("Q", 27),
+    ("J", 24),
+    ("E", 9),
+    ("A", 8),
+    ("I", 7),
+    ("F", 6),
+    ("T", 5),
+];
+
+const AARCH64_FLAGS: &[(&str, u32)] = &[
+    ("N", 31),
+    ("Z", 30),
+    ("C", 29),
+    ("V", 28),
+    ("SS", 21),
+    ("IL", 20),
+    ("D", 9),
+    ("A", 8),
+    ("I", 7),
+    ("F", 6),
+];
+
+/// Flag names and bit positions
+type FlagBits = &'static [(&'static str, u32)];
+
+struct FlagsLayout {
+    /// Flags register names to try, adapters disagree
+    registers: &'static [&'static str],
+    bits: FlagBits,
+}
+
+fn flags_layout(arch: &str) -> Option<FlagsLayout> {
+    let (registers, bits): (&'static [&'static str], FlagBits) = match arch {
+        "x86_64" => (&["rflags", "eflags"], X86_FLAGS),
+        "x86" => (&["eflags"], X86_FLAGS),
+        "aarch64" => (&["cpsr", "nzcv", "pstate"], AARCH64_FLAGS),
+        "armv7" | "armv7eb" | "thumb2" | "thumb2eb" => (&["cpsr"], ARM_FLAGS),
+        _ => return None,
+    };
+    Some(FlagsLayout { registers, bits })
+}
+
+/// Decoded flags register, edits are local until passed to `set_flags`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flags {
+    pub register: String,
+    pub value: u64,
+    bits: FlagBits,
+}
+
+impl Flags {
+    fn bit(&self, name: &str) -> Option<u32> {
+        self.bits
+            .iter()
+            .find(|(flag, _)| flag.eq_ignore_ascii_case(name))
+            .map(|&(_, bit)| bit)
+    }
+
+    /// State of a flag by name (case insensitive), None if the architecture has no such flag
+    pub fn get(&self, name: &str) -> Option<bool> {
+        Some(self.value & (1 << self.bit(name)?) != 0)
+    }
+
+    pub fn set(&mut self, name: &str, state: bool) -> bool {
+        let Some(bit) = self.bit(name) else {
+            return false;
+        };
+        if state {
+            self.value |= 1 << bit;
+        } else {
+            self.value &= !(1 << bit);
+        }
+        true
+    }
+
+    /// Every known flag and its state
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, bool)> + '_ {
+        self.bits
+            .iter()
+            .map(|&(name, bit)| (name, self.value & (1 << bit) != 0))
+    }
+
+    /// Names of the flags that are set
+    pub fn active(&self) -> Vec<&'static str> {
+        self.iter()
+            .filter(|&(_, state)| state)
+            .map(|(name, _)| name)
+            .collect()
+    }
+}
+
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} = 0x{:x} [{}]",
+            self.register,
+            self.value,
+            self.active().join(" ")
+        )
+    }
+}
+
+impl DebuggerController {
+    /// Read and decode the flags register of the target architecture
+    pub fn flags(&self) -> Option<Flags> {
+        let layout = flags_layout(&self.arch_name()?)?;
+        layout.registers.iter().find_map(|&name| {
+            Some(Flags {
+                register: name.to_owned(),
+                value: self.reg_u64(name)?,
+                bits: layout.bits,
+            })
+        })
+    }
+
+    /// Write back a (modified) flags value
+    pub fn set_flags(&self, flags: &Flags) -> bool {
+        self.set_reg_u64(&flags.register, flags.value)
+    }
+
+    /// State of a single flag, e.g. `flag("ZF")`
+    pub fn flag(&self, name: &str) -> Option<bool> {
+        self.flags()?.get(name)
+    }
+
+    /// Set or clear a single flag, leaving the others untouched
+    pub fn set_flag(&self, name: &str, state: bool) -> bool {
+        let Some(mut flags) = self.flags() else {
+            return false;
+        };
+        if !flags.set(name, state) {
+            log::warn!("{} has no flag {}", flags.register, name);
+            return false;
+        }
+        self.set_flags(&flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flags(arch: &str, value: u64) -> Flags {
+        let layout = flags_layout(arch).unwrap();
+        Flags {
+            register: layout.registers[0].to_owned(),
+            value,
+            bits: layout.bits,
+        }
+    }
+
+    #[test]
+    fn test_flags_get_set() {
+        // ZF and PF set, reserved bit 1 always on
+        let mut f = flags("x86_64", 0x246);
+        assert_eq!(f.register, "rflags");
+        assert_eq!(f.get("ZF"), Some(true));
+        assert_eq!(f.get("zf"), Some(true));
+        assert_eq!(f.get("CF"), Some(false));
+        assert_eq!(f.get("N"), None);
+        assert_eq!(f.active(), ["PF", "ZF", "IF"]);
+
+        assert!(f.set("CF", true));
+        assert!(f.set("ZF", false));
+        assert!(!f.set("XX", true));
+        assert_eq!(f.value, 0x207);
+        assert!(f.set("ID", true));
+        assert_eq!(f.value, 0x20_0207);
+        assert_eq!(f.to_string(), "rflags = 0x200207 [CF PF IF ID]");
+
+        // the top bit of a 32-bit status register
+        let mut f = flags("aarch64", 0x6000_0000);
+        assert_eq!(f.get("Z"), Some(true));
+        assert_eq!(f.get("N"), Some(false));
+        assert!(f.set("N", true));
+        assert_eq!(f.value, 0xe000_0000);
+        assert!(f.set("Z", false));
+        assert_eq!(f.value, 0xa000_0000);
+        assert!(flags_layout("mips32").is_none());
+    }
+}
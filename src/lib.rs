@@ -8,23 +8,78 @@
 use binaryninja::binary_view::BinaryView;
 use std::ffi::{c_void, CStr, CString};
 use std::fmt;
-use std::sync::Arc;
-
+use std::sync::atomic::AtomicU8;
+use std::sync::{Arc, Weak};
+
+mod abi;
+pub mod backtrace;
+mod cache;
+pub mod call;
+pub mod coverage;
+mod elf;
 pub mod ffi;
+pub mod flags;
+mod lift;
+pub mod memory;
+pub mod modules;
+pub mod patch;
+pub mod registers;
+pub mod snapshot;
+pub mod symbols;
+pub mod tenet;
+pub mod threads;
+pub mod trace;
+mod types;
+pub mod unwind;
+pub mod values;
 
 //common types
+pub use backtrace::{BacktraceFrame, FrameVariable};
+pub use call::CallTarget;
+pub use coverage::{Coverage, CoverageModule, CoverageRecorder, CoveredBlock};
 pub use ffi::{
     BNDebugAdapterConnectionStatus, BNDebugAdapterTargetStatus, BNDebugStopReason,
     BNDebuggerEventType, BNFunctionGraphType,
 };
+pub use flags::Flags;
+pub use memory::{MemoryPermissions, MemoryRegion, MemorySpan, PartialRead, RegionKind};
+pub use modules::{FileMatch, ModuleMetadata, ModuleSegment, ModuleWatcher};
+pub use patch::{Patch, PatchId};
+pub use registers::{RegisterChange, RegisterSnapshot, RegisterValue};
+pub use snapshot::{MemoryChange, MemorySnapshot, TypeAnnotation};
+pub use symbols::SymbolLocation;
+pub use tenet::TenetWriter;
+pub use threads::{
+    SchedulerLock, ThreadEvent, ThreadEventKind, ThreadGuard, ThreadRecord, ThreadTracker,
+};
+pub use trace::{
+    AccessKind, MemoryAccess, TraceEntry, TraceMode, TraceReader, TraceRecorder, TraceSink,
+    TraceStop, TraceWriter,
+};
+pub use unwind::{UnwindMethod, UnwoundFrame};
+pub use values::{CapturedReturn, FunctionArg, TypedValue, ValueLocation};
 
 struct DebuggerControllerInner {
     handle: *mut ffi::BNDebuggerController,
+    cache: Arc<cache::StopCache>,
+    patches: patch::PatchJournal,
+    scheduler_lock: AtomicU8,
+    unwind: unwind::UnwindCache,
+    module_views: modules::ModuleViews,
+    symbols: symbols::SymbolCache,
+    register_widths: registers::RegisterWidths,
+    loader_hooks: modules::LoaderHooks,
 }
 
 impl Drop for DebuggerControllerInner {
     fn drop(&mut self) {
         unsafe {
+            if let Some(index) = self.cache.take_callback() {
+                ffi::BNDebuggerRemoveEventCallback(self.handle, index);
+            }
+            if let Some(index) = self.symbols.take_callback() {
+                ffi::BNDebuggerRemoveEventCallback(self.handle, index);
+            }
             ffi::BNDebuggerFreeController(self.handle);
         }
     }
@@ -39,6 +94,21 @@ pub struct DebuggerController {
     inner: Arc<DebuggerControllerInner>,
 }
 
+/// Non-owning controller reference for event callbacks, the core keeps
+/// callbacks alive so holding a `DebuggerController` there would leak it
+#[derive(Clone)]
+pub(crate) struct WeakController {
+    inner: Weak<DebuggerControllerInner>,
+}
+
+impl WeakController {
+    pub(crate) fn upgrade(&self) -> Option<DebuggerController> {
+        self.inner
+            .upgrade()
+            .map(|inner| DebuggerController { inner })
+    }
+}
+
 impl DebuggerController {
     /// get or create a debugger controller for a binary view
     pub fn new(bv: &BinaryView) -> Option<Self> {
@@ -47,7 +117,17 @@ impl DebuggerController {
             None
         } else {
             Some(Self {
-                inner: Arc::new(DebuggerControllerInner { handle }),
+                inner: Arc::new(DebuggerControllerInner {
+                    handle,
+                    cache: Default::default(),
+                    patches: Default::default(),
+                    scheduler_lock: Default::default(),
+                    unwind: Default::default(),
+                    module_views: Default::default(),
+                    symbols: Default::default(),
+                    register_widths: Default::default(),
+                    loader_hooks: Default::default(),
+                }),
             })
         }
     }
@@ -62,6 +142,12 @@ impl DebuggerController {
         self.inner.handle
     }
 
+    pub(crate) fn downgrade(&self) -> WeakController {
+        WeakController {
+            inner: Arc::downgrade(&self.inner),
+        }
+    }
+
     /// Get the live BinaryView used during debugging.
     ///
     /// This returns the rebased view with actual runtime addresses
@@ -104,6 +190,11 @@ impl DebuggerController {
         unsafe { binaryninjacore_sys::BNNewViewReference(handle as *mut _) as *mut _ }
     }
 
+    /// Live view handle released on drop
+    pub(crate) fn live_view(&self) -> Option<types::CoreView> {
+        unsafe { types::CoreView::from_raw(self.data_handle() as *mut _) }
+    }
+
     pub fn is_connected(&self) -> bool {
         unsafe { ffi::BNDebuggerIsConnected(self.handle()) }
     }
@@ -191,10 +282,13 @@ impl DebuggerController {
     /// non blocking
 
     pub fn launch(&self) -> bool {
+        self.inner.cache.invalidate();
+        self.clear_patches();
         unsafe { ffi::BNDebuggerLaunch(self.handle()) }
     }
 
     pub fn go(&self) -> bool {
+        self.inner.cache.invalidate();
         unsafe { ffi::BNDebuggerGo(self.handle()) }
     }
 
@@ -203,64 +297,106 @@ impl DebuggerController {
     }
 
     pub fn quit(&self) {
+        self.restore_patches_on_exit();
         unsafe { ffi::BNDebuggerQuit(self.handle()) }
     }
 
     pub fn restart(&self) {
+        self.inner.cache.invalidate();
+        self.clear_patches();
         unsafe { ffi::BNDebuggerRestart(self.handle()) }
     }
 
     pub fn detach(&self) {
+        self.restore_patches_on_exit();
         unsafe { ffi::BNDebuggerDetach(self.handle()) }
     }
 
     pub fn step_into(&self, il: BNFunctionGraphType) -> bool {
+        self.warn_scheduler_unlocked("step_into", SchedulerLock::Step);
+        self.inner.cache.invalidate();
         unsafe { ffi::BNDebuggerStepInto(self.handle(), il) }
     }
 
     pub fn step_over(&self, il: BNFunctionGraphType) -> bool {
+        self.warn_scheduler_unlocked("step_over", SchedulerLock::Step);
+        self.inner.cache.invalidate();
         unsafe { ffi::BNDebuggerStepOver(self.handle(), il) }
     }
 
     pub fn step_return(&self) -> bool {
+        self.warn_scheduler_unlocked("step_return", SchedulerLock::Step);
+        self.inner.cache.invalidate();
         unsafe { ffi::BNDebuggerStepReturn(self.handle()) }
     }
 
 
     pub fn launch_and_wait(&self) -> BNDebugStopReason {
-        unsafe { ffi::BNDebuggerLaunchAndWait(self.handle()) }
+        self.clear_patches();
+        let reason = unsafe { ffi::BNDebuggerLaunchAndWait(self.handle()) };
+        self.inner.cache.invalidate();
+        reason
     }
 
     pub fn go_and_wait(&self) -> BNDebugStopReason {
-        unsafe { ffi::BNDebuggerGoAndWait(self.handle()) }
+        loop {
+            let reason = unsafe { ffi::BNDebuggerGoAndWait(self.handle()) };
+            self.inner.cache.invalidate();
+            if !self.handle_loader_stop(reason) {
+                return reason;
+            }
+        }
     }
 
     pub fn pause_and_wait(&self) -> BNDebugStopReason {
-        unsafe { ffi::BNDebuggerPauseAndWait(self.handle()) }
+        let reason = unsafe { ffi::BNDebuggerPauseAndWait(self.handle()) };
+        self.inner.cache.invalidate();
+        reason
     }
 
     pub fn quit_and_wait(&self) {
+        self.restore_patches_on_exit();
         unsafe { ffi::BNDebuggerQuitAndWait(self.handle()) }
     }
 
     pub fn step_into_and_wait(&self, il: BNFunctionGraphType) -> BNDebugStopReason {
-        unsafe { ffi::BNDebuggerStepIntoAndWait(self.handle(), il) }
+        let _lock = self.lock_scheduler(SchedulerLock::Step);
+        let reason = unsafe { ffi::BNDebuggerStepIntoAndWait(self.handle(), il) };
+        self.inner.cache.invalidate();
+        reason
     }
 
     pub fn step_over_and_wait(&self, il: BNFunctionGraphType) -> BNDebugStopReason {
-        unsafe { ffi::BNDebuggerStepOverAndWait(self.handle(), il) }
+        let _lock = self.lock_scheduler(SchedulerLock::Step);
+        let reason = unsafe { ffi::BNDebuggerStepOverAndWait(self.handle(), il) };
+        self.inner.cache.invalidate();
+        reason
     }
 
     pub fn step_return_and_wait(&self) -> BNDebugStopReason {
-        unsafe { ffi::BNDebuggerStepReturnAndWait(self.handle()) }
+        let _lock = self.lock_scheduler(SchedulerLock::Step);
+        let reason = unsafe { ffi::BNDebuggerStepReturnAndWait(self.handle()) };
+        self.inner.cache.invalidate();
+        reason
     }
 
     pub fn run_to(&self, addresses: &[u64]) -> bool {
+        self.warn_scheduler_unlocked("run_to", SchedulerLock::On);
+        self.inner.cache.invalidate();
         unsafe { ffi::BNDebuggerRunTo(self.handle(), addresses.as_ptr(), addresses.len()) }
     }
 
     pub fn run_to_and_wait(&self, addresses: &[u64]) -> BNDebugStopReason {
-        unsafe { ffi::BNDebuggerRunToAndWait(self.handle(), addresses.as_ptr(), addresses.len()) }
+        let _lock = self.lock_scheduler(SchedulerLock::On);
+        loop {
+            let reason = unsafe {
+                ffi::BNDebuggerRunToAndWait(self.handle(), addresses.as_ptr(), addresses.len())
+            };
+            self.inner.cache.invalidate();
+            if !self.handle_loader_stop(reason) {
+                return reason;
+            }
+        }
     }
 
     /// regs
@@ -274,7 +410,9 @@ impl DebuggerController {
     }
 
     pub fn set_ip(&self, address: u64) -> bool {
-        unsafe { ffi::BNDebuggerSetIP(self.handle(), address) }
+        let ok = unsafe { ffi::BNDebuggerSetIP(self.handle(), address) };
+        self.inner.cache.invalidate_registers();
+        ok
     }
 
     pub fn stack_pointer(&self) -> u64 {
@@ -282,6 +420,13 @@ impl DebuggerController {
     }
 
     pub fn registers(&self) -> Vec<DebugRegister> {
+        if self.inner.cache.is_enabled() {
+            return self.inner.cache.registers(|| self.registers_uncached());
+        }
+        self.registers_uncached()
+    }
+
+    fn registers_uncached(&self) -> Vec<DebugRegister> {
         let mut count = 0usize;
         let ptr = unsafe { ffi::BNDebuggerGetRegisters(self.handle(), &mut count) };
         if ptr.is_null() {
@@ -299,23 +444,71 @@ impl DebuggerController {
         result
     }
 
+    /// Raw little-endian register bytes, truncated to the register's width when it is known
     pub fn get_register_value(&self, name: &str) -> Vec<u8> {
+        if self.inner.cache.is_enabled() {
+            return self
+                .inner
+                .cache
+                .register_value(name, || self.get_register_value_uncached(name));
+        }
+        self.get_register_value_uncached(name)
+    }
+
+    fn get_register_value_uncached(&self, name: &str) -> Vec<u8> {
         let name_cstr = CString::new(name).unwrap();
-        let mut buffer = [0u8; 64];
+        // the core always stores a full 512-bit value
+        let mut buffer = [0u8; registers::MAX_REGISTER_SIZE];
         unsafe {
             ffi::BNDebuggerGetRegisterValue(self.handle(), name_cstr.as_ptr(), buffer.as_mut_ptr());
         }
-        buffer.to_vec()
+        let width = self.register_width(name).unwrap_or(buffer.len());
+        buffer[..width].to_vec()
     }
 
+    /// Write raw little-endian register bytes, shorter values are zero extended.
+    ///
+    /// Fails if `value` is wider than the register.
     pub fn set_register_value(&self, name: &str, value: &[u8]) -> bool {
+        let width = self
+            .register_width(name)
+            .unwrap_or(registers::MAX_REGISTER_SIZE);
+        if value.len() > width {
+            log::warn!(
+                "{} bytes do not fit in {} ({} bytes wide)",
+                value.len(),
+                name,
+                width
+            );
+            return false;
+        }
+        // the core reads a full 512-bit value regardless of the register width
+        let mut buffer = [0u8; registers::MAX_REGISTER_SIZE];
+        buffer[..value.len()].copy_from_slice(value);
+
         let name_cstr = CString::new(name).unwrap();
-        unsafe { ffi::BNDebuggerSetRegisterValue(self.handle(), name_cstr.as_ptr(), value.as_ptr()) }
+        let result = unsafe {
+            ffi::BNDebuggerSetRegisterValue(self.handle(), name_cstr.as_ptr(), buffer.as_ptr())
+        };
+        // sub-registers alias each other (eax/rax), drop all cached values
+        self.inner.cache.invalidate_registers();
+        result
     }
 
     /// mem
 
     pub fn read_memory(&self, address: u64, size: usize) -> Option<Vec<u8>> {
+        if self.inner.cache.is_enabled() {
+            return self
+                .inner
+                .cache
+                .read(address, size, |addr, len| self.read_memory_uncached(addr, len));
+        }
+        self.read_memory_uncached(address, size)
+    }
+
+    /// Read memory directly from the adapter, bypassing the cache
+    pub fn read_memory_uncached(&self, address: u64, size: usize) -> Option<Vec<u8>> {
         let ptr = unsafe { ffi::BNDebuggerReadMemory(self.handle(), address, size) };
         if ptr.is_null() {
             return None;
@@ -334,15 +527,32 @@ impl DebuggerController {
         Some(data)
     }
 
+    /// Write memory, recording the original bytes when the patch journal is enabled
     pub fn write_memory(&self, address: u64, data: &[u8]) -> bool {
+        if self.inner.patches.is_enabled() {
+            return self.patch_memory(address, data).is_some();
+        }
+        self.write_memory_unjournaled(address, data)
+    }
+
+    fn write_memory_unjournaled(&self, address: u64, data: &[u8]) -> bool {
         unsafe {
             let buffer = binaryninjacore_sys::BNCreateDataBuffer(data.as_ptr() as *const _, data.len());
             let result = ffi::BNDebuggerWriteMemory(self.handle(), address, buffer as *mut _);
             binaryninjacore_sys::BNFreeDataBuffer(buffer);
+            if result {
+                self.inner.cache.write(address, data);
+            }
             result
         }
     }
 
+    // process
+
+    pub fn active_pid(&self) -> u32 {
+        unsafe { ffi::BNDebuggerGetActivePID(self.handle()) }
+    }
+
     /// threads
 
     pub fn threads(&self) -> Vec<DebugThread> {
@@ -369,6 +579,8 @@ impl DebuggerController {
 
     pub fn set_active_thread(&self, thread: &DebugThread) {
         unsafe { ffi::BNDebuggerSetActiveThread(self.handle(), thread.to_raw()) }
+        // cached registers belong to the previous thread
+        self.inner.cache.invalidate_registers();
     }
 
     pub fn suspend_thread(&self, tid: u32) -> bool {
@@ -419,6 +631,29 @@ impl DebuggerController {
         result
     }
 
+    /// Module name and offset of an absolute address, None outside every module
+    pub fn absolute_to_relative(&self, address: u64) -> Option<(String, u64)> {
+        let relative = unsafe { ffi::BNDebuggerAbsoluteAddressToRelative(self.handle(), address) };
+        if relative.module.is_null() {
+            return None;
+        }
+        let module = unsafe {
+            let s = CStr::from_ptr(relative.module)
+                .to_string_lossy()
+                .into_owned();
+            ffi::BNDebuggerFreeString(relative.module);
+            s
+        };
+        (!module.is_empty()).then_some((module, relative.offset))
+    }
+
+    pub fn relative_to_absolute(&self, module: &str, offset: u64) -> u64 {
+        let module_cstr = CString::new(module).unwrap();
+        unsafe {
+            ffi::BNDebuggerRelativeAddressToAbsolute(self.handle(), module_cstr.as_ptr(), offset)
+        }
+    }
+
     /// bps
 
     pub fn breakpoints(&self) -> Vec<DebugBreakpoint> {
@@ -664,6 +899,7 @@ impl fmt::Display for DebugModule {
 pub struct DebugRegister {
     pub name: String,
     pub value: Vec<u8>,
+    /// Width in bits, as reported by the adapter
     pub width: usize,
     pub register_index: usize,
     pub hint: String,
@@ -671,8 +907,8 @@ pub struct DebugRegister {
 
 impl DebugRegister {
     fn from_raw(raw: &ffi::BNDebugRegister) -> Self {
-        // Cap width at buffer size (64 bytes)
-        let width = raw.m_width.min(64);
+        // m_width is in bits, cap at buffer size (64 bytes)
+        let width = raw.m_width.div_ceil(8).min(registers::MAX_REGISTER_SIZE);
         Self {
             name: if raw.m_name.is_null() {
                 String::new()
@@ -682,7 +918,7 @@ impl DebugRegister {
                     .into_owned()
             },
             value: raw.m_value[..width].to_vec(),
-            width: raw.m_width,  // Keep original width for display
+            width: raw.m_width, // Keep original width for display
             register_index: raw.m_registerIndex,
             hint: if raw.m_hint.is_null() {
                 String::new()
@@ -694,6 +930,11 @@ impl DebugRegister {
         }
     }
 
+    /// Width in bytes
+    pub fn size(&self) -> usize {
+        self.value.len()
+    }
+
     /// Get value as u64 (for registers <= 8 bytes)
     pub fn value_u64(&self) -> u64 {
         let mut buf = [0u8; 8];
@@ -701,11 +942,15 @@ impl DebugRegister {
         buf[..len].copy_from_slice(&self.value[..len]);
         u64::from_le_bytes(buf)
     }
+
+    pub fn typed_value(&self) -> Option<RegisterValue> {
+        RegisterValue::from_bytes(&self.value)
+    }
 }
 
 impl fmt::Display for DebugRegister {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.width <= 8 {
+        if self.size() <= 8 {
             write!(f, "{} = 0x{:x}", self.name, self.value_u64())
         } else {
             write!(f, "{} = {:02x?}", self.name, self.value)
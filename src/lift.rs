@@ -0,0 +1,380 @@
+// memory accesses of single instructions, from their lifted LLIL and the current registers
+
+use crate::registers::RegisterSnapshot;
+use crate::types::core_string;
+use crate::DebuggerController;
+use binaryninjacore_sys as sys;
+use binaryninjacore_sys::{BNLowLevelILInstruction, BNLowLevelILOperation, BNRegisterInfo};
+use std::collections::HashMap;
+
+/// Temporary LLIL registers have this bit set
+const TEMP_REGISTER: u32 = 0x8000_0000;
+
+/// A memory access of an instruction about to execute
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PlannedAccess {
+    pub write: bool,
+    pub address: u64,
+    pub size: usize,
+}
+
+/// LLIL of one instruction, lifted on its own without a function
+struct Lifted {
+    il: *mut sys::BNLowLevelILFunction,
+}
+
+impl Drop for Lifted {
+    fn drop(&mut self) {
+        unsafe { sys::BNFreeLowLevelILFunction(self.il) }
+    }
+}
+
+/// Name and sub-register layout of architecture registers
+struct Registers {
+    arch: *mut sys::BNArchitecture,
+    info: HashMap<u32, Option<(String, BNRegisterInfo)>>,
+}
+
+impl Registers {
+    fn get(&mut self, reg: u32) -> Option<&(String, BNRegisterInfo)> {
+        let arch = self.arch;
+        self.info
+            .entry(reg)
+            .or_insert_with(|| unsafe {
+                let name = core_string(sys::BNGetArchitectureRegisterName(arch, reg))?;
+                Some((name, sys::BNGetArchitectureRegisterInfo(arch, reg)))
+            })
+            .as_ref()
+    }
+}
+
+/// Decodes the memory accesses of instructions from their LLIL, caching the
+/// lifted IL by address
+pub(crate) struct Lifter {
+    arch: *mut sys::BNArchitecture,
+    registers: Registers,
+    lifted: HashMap<u64, Option<Lifted>>,
+}
+
+impl Lifter {
+    pub fn new(arch: *mut sys::BNArchitecture) -> Self {
+        Self {
+            arch,
+            registers: Registers {
+                arch,
+                info: HashMap::new(),
+            },
+            lifted: HashMap::new(),
+        }
+    }
+
+    /// Whether the instruction at `pc` is a call, which pushes its return
+    /// address without an explicit LLIL store on some architectures
+    pub fn is_call(&mut self, dbg: &DebuggerController, pc: u64) -> bool {
+        let Some(lifted) = self.lift(dbg, pc) else {
+            return false;
+        };
+        unsafe {
+            (0..sys::BNGetLowLevelILInstructionCount(lifted.il)).any(|i| {
+                let index = sys::BNGetLowLevelILIndexForInstruction(lifted.il, i);
+                matches!(
+                    sys::BNGetLowLevelILByIndex(lifted.il, index).operation,
+                    BNLowLevelILOperation::LLIL_CALL
+                        | BNLowLevelILOperation::LLIL_CALL_STACK_ADJUST
+                )
+            })
+        }
+    }
+
+    /// Memory the instruction at `pc` is about to read and write, evaluated
+    /// against the registers before it executes.
+    ///
+    /// Address expressions using values the evaluator cannot compute are
+    /// dropped, and so is everything after conditional control flow inside
+    /// the instruction (e.g. `cmov` or `rep` prefixes lifted to IF/GOTO).
+    pub fn accesses(
+        &mut self,
+        dbg: &DebuggerController,
+        pc: u64,
+        registers: &RegisterSnapshot,
+    ) -> Vec<PlannedAccess> {
+        let arch = self.arch;
+        if self.lift(dbg, pc).is_none() {
+            return Vec::new();
+        }
+        let Self {
+            registers: names,
+            lifted,
+            ..
+        } = self;
+        let Some(Some(lifted)) = lifted.get(&pc) else {
+            return Vec::new();
+        };
+        let mut eval = Eval {
+            dbg,
+            il: lifted.il,
+            names,
+            snapshot: registers,
+            sp: unsafe { sys::BNGetArchitectureStackPointerRegister(arch) },
+            overrides: HashMap::new(),
+            accesses: Vec::new(),
+        };
+        unsafe {
+            for i in 0..sys::BNGetLowLevelILInstructionCount(lifted.il) {
+                let index = sys::BNGetLowLevelILIndexForInstruction(lifted.il, i);
+                if !eval.instruction(index) {
+                    break;
+                }
+            }
+        }
+        eval.accesses
+    }
+
+    fn lift(&mut self, dbg: &DebuggerController, pc: u64) -> Option<&Lifted> {
+        let arch = self.arch;
+        self.lifted
+            .entry(pc)
+            .or_insert_with(|| unsafe {
+                let max_length = sys::BNGetArchitectureMaxInstructionLength(arch);
+                let data = dbg.read_memory(pc, max_length)?;
+                let il = sys::BNCreateLowLevelILFunction(arch, std::ptr::null_mut());
+                if il.is_null() {
+                    return None;
+                }
+                let lifted = Lifted { il };
+                sys::BNLowLevelILSetCurrentAddress(il, arch, pc);
+                let mut length = data.len();
+                if !sys::BNGetInstructionLowLevelIL(arch, data.as_ptr(), pc, &mut length, il) {
+                    return None;
+                }
+                sys::BNFinalizeLowLevelILFunction(il);
+                Some(lifted)
+            })
+            .as_ref()
+    }
+}
+
+/// Evaluates LLIL expressions of one instruction, tracking the registers it sets
+struct Eval<'a> {
+    dbg: &'a DebuggerController,
+    il: *mut sys::BNLowLevelILFunction,
+    names: &'a mut Registers,
+    snapshot: &'a RegisterSnapshot,
+    sp: u32,
+    /// Registers set by earlier LLIL instructions, None once unknown
+    overrides: HashMap<u32, Option<u64>>,
+    accesses: Vec<PlannedAccess>,
+}
+
+fn mask(value: u64, size: usize) -> u64 {
+    if size == 0 || size >= 8 {
+        value
+    } else {
+        value & ((1u64 << (size * 8)) - 1)
+    }
+}
+
+fn sign_extend(value: u64, size: usize) -> u64 {
+    if size == 0 || size >= 8 {
+        return value;
+    }
+    let shift = 64 - size * 8;
+    (((value << shift) as i64) >> shift) as u64
+}
+
+impl Eval<'_> {
+    fn expr(&self, index: u64) -> BNLowLevelILInstruction {
+        unsafe { sys::BNGetLowLevelILByIndex(self.il, index as usize) }
+    }
+
+    /// Process one LLIL instruction, false to stop at conditional control flow
+    fn instruction(&mut self, index: usize) -> bool {
+        use BNLowLevelILOperation::*;
+        let instr = self.expr(index as u64);
+        let [a, b, c, _] = instr.operands;
+        match instr.operation {
+            LLIL_SET_REG => {
+                let value = self.eval(b);
+                self.set_register(a as u32, value);
+            }
+            LLIL_SET_REG_SPLIT => {
+                self.eval(c);
+                self.set_register(a as u32, None);
+                self.set_register(b as u32, None);
+            }
+            LLIL_SET_FLAG => {
+                self.eval(b);
+            }
+            LLIL_STORE => {
+                let address = self.eval(a);
+                self.eval(b);
+                if let Some(address) = address {
+                    self.access(true, address, instr.size);
+                }
+            }
+            LLIL_PUSH => {
+                self.eval(a);
+                let sp = self
+                    .register(self.sp)
+                    .map(|sp| sp.wrapping_sub(instr.size as u64));
+                self.set_register(self.sp, sp);
+                if let Some(sp) = sp {
+                    self.access(true, sp, instr.size);
+                }
+            }
+            LLIL_JUMP
+            | LLIL_JUMP_TO
+            | LLIL_CALL
+            | LLIL_CALL_STACK_ADJUST
+            | LLIL_TAILCALL
+            | LLIL_RET => {
+                self.eval(a);
+            }
+            LLIL_IF => {
+                self.eval(a);
+                return false;
+            }
+            LLIL_GOTO => return false,
+            _ => {}
+        }
+        true
+    }
+
+    fn eval(&mut self, index: u64) -> Option<u64> {
+        use BNLowLevelILOperation::*;
+        let expr = self.expr(index);
+        let [a, b, _, _] = expr.operands;
+        let value = match expr.operation {
+            LLIL_CONST | LLIL_CONST_PTR => Some(a),
+            LLIL_EXTERN_PTR => Some(a.wrapping_add(b)),
+            LLIL_REG => self.register(a as u32),
+            LLIL_LOAD => {
+                let address = self.eval(a)?;
+                self.access(false, address, expr.size);
+                let data = self.dbg.read_memory(address, expr.size.min(8))?;
+                let mut bytes = [0u8; 8];
+                bytes[..data.len()].copy_from_slice(&data);
+                Some(u64::from_le_bytes(bytes))
+            }
+            LLIL_POP => {
+                let sp = self.register(self.sp)?;
+                self.set_register(self.sp, Some(sp.wrapping_add(expr.size as u64)));
+                self.access(false, sp, expr.size);
+                None
+            }
+            LLIL_ADD | LLIL_SUB | LLIL_AND | LLIL_OR | LLIL_XOR | LLIL_LSL | LLIL_LSR
+            | LLIL_ASR | LLIL_MUL => {
+                // both sides, for the loads they contain
+                let (x, y) = (self.eval(a), self.eval(b));
+                let (x, y) = (x?, y?);
+                Some(match expr.operation {
+                    LLIL_ADD => x.wrapping_add(y),
+                    LLIL_SUB => x.wrapping_sub(y),
+                    LLIL_AND => x & y,
+                    LLIL_OR => x | y,
+                    LLIL_XOR => x ^ y,
+                    LLIL_LSL => x.wrapping_shl(y as u32),
+                    LLIL_LSR => x.wrapping_shr(y as u32),
+                    LLIL_ASR => (sign_extend(x, expr.size) as i64).wrapping_shr(y as u32) as u64,
+                    _ => x.wrapping_mul(y),
+                })
+            }
+            LLIL_NEG => self.eval(a).map(|x| x.wrapping_neg()),
+            LLIL_NOT => self.eval(a).map(|x| !x),
+            LLIL_ZX | LLIL_LOW_PART => self.eval(a),
+            LLIL_SX => {
+                let size = self.expr(a).size;
+                self.eval(a).map(|x| sign_extend(x, size))
+            }
+            LLIL_ADC | LLIL_SBB | LLIL_ROL | LLIL_RLC | LLIL_ROR | LLIL_RRC | LLIL_MULU_DP
+            | LLIL_MULS_DP | LLIL_DIVU | LLIL_DIVU_DP | LLIL_DIVS | LLIL_DIVS_DP | LLIL_MODU
+            | LLIL_MODU_DP | LLIL_MODS | LLIL_MODS_DP | LLIL_CMP_E | LLIL_CMP_NE | LLIL_CMP_SLT
+            | LLIL_CMP_ULT | LLIL_CMP_SLE | LLIL_CMP_ULE | LLIL_CMP_SGE | LLIL_CMP_UGE
+            | LLIL_CMP_SGT | LLIL_CMP_UGT | LLIL_TEST_BIT | LLIL_ADD_OVERFLOW | LLIL_FADD
+            | LLIL_FSUB | LLIL_FMUL | LLIL_FDIV | LLIL_FCMP_E | LLIL_FCMP_NE | LLIL_FCMP_LT
+            | LLIL_FCMP_LE | LLIL_FCMP_GE | LLIL_FCMP_GT | LLIL_FCMP_O | LLIL_FCMP_UO => {
+                self.eval(a);
+                self.eval(b);
+                None
+            }
+            LLIL_BOOL_TO_INT | LLIL_FSQRT | LLIL_FNEG | LLIL_FABS | LLIL_FLOAT_TO_INT
+            | LLIL_INT_TO_FLOAT | LLIL_FLOAT_CONV | LLIL_ROUND_TO_INT | LLIL_FLOOR | LLIL_CEIL
+            | LLIL_FTRUNC => {
+                self.eval(a);
+                None
+            }
+            _ => None,
+        };
+        value.map(|value| mask(value, expr.size))
+    }
+
+    fn access(&mut self, write: bool, address: u64, size: usize) {
+        if size > 0 {
+            self.accesses.push(PlannedAccess {
+                write,
+                address,
+                size,
+            });
+        }
+    }
+
+    fn register(&mut self, reg: u32) -> Option<u64> {
+        if let Some(value) = self.overrides.get(&reg) {
+            return *value;
+        }
+        if reg & TEMP_REGISTER != 0 {
+            return None;
+        }
+        let (name, info) = self.names.get(reg)?.clone();
+        let full = info.fullWidthRegister;
+        let extract =
+            |value: u64| Some(mask(value.checked_shr(info.offset as u32 * 8)?, info.size));
+        if full != reg {
+            if let Some(value) = self.overrides.get(&full) {
+                return extract((*value)?);
+            }
+        }
+        if let Some(reg) = self.snapshot.get(&name) {
+            return Some(reg.value_u64());
+        }
+        if full == reg {
+            return None;
+        }
+        let (full_name, _) = self.names.get(full)?;
+        extract(self.snapshot.get(full_name)?.value_u64())
+    }
+
+    fn set_register(&mut self, reg: u32, value: Option<u64>) {
+        self.overrides.insert(reg, value);
+        if reg & TEMP_REGISTER != 0 {
+            return;
+        }
+        let Some(&(_, info)) = self.names.get(reg) else {
+            return;
+        };
+        if info.fullWidthRegister != reg {
+            // a partial write leaves the rest of the full register as is
+            self.overrides.insert(info.fullWidthRegister, None);
+        } else {
+            // sub-registers now alias the new value
+            let stale: Vec<u32> = self
+                .overrides
+                .keys()
+                .copied()
+                .filter(|&other| {
+                    other != reg
+                        && other & TEMP_REGISTER == 0
+                        && self
+                            .names
+                            .info
+                            .get(&other)
+                            .and_then(|info| info.as_ref())
+                            .is_some_and(|(_, info)| info.fullWidthRegister == reg)
+                })
+                .collect();
+            for other in stale {
+                self.overrides.remove(&other);
+            }
+        }
+    }
+}
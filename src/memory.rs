@@ -0,0 +1,619 @@
+// runtime memory map of the target and fault tolerant reads
+
+use crate::DebuggerController;
+use rayon::prelude::*;
+use std::fmt;
+use std::path::Path;
+
+pub const PAGE_SIZE: u64 = 0x1000;
+
+/// Chunk size used by `read_memory_parallel`
+pub const PARALLEL_CHUNK_SIZE: u64 = 0x10_0000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryPermissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+    pub shared: bool,
+}
+
+impl MemoryPermissions {
+    /// Parse a `rwxp` style permission string (as found in /proc/<pid>/maps)
+    pub fn parse(s: &str) -> Option<Self> {
+        let b = s.as_bytes();
+        if b.len() < 3 {
+            return None;
+        }
+        let flag = |c: u8, want: u8| match c {
+            b'-' => Some(false),
+            c if c == want => Some(true),
+            _ => None,
+        };
+        Some(Self {
+            read: flag(b[0], b'r')?,
+            write: flag(b[1], b'w')?,
+            execute: flag(b[2], b'x')?,
+            shared: b.get(3) == Some(&b's'),
+        })
+    }
+
+    pub fn is_none(&self) -> bool {
+        !self.read && !self.write && !self.execute
+    }
+}
+
+impl fmt::Display for MemoryPermissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}{}",
+            if self.read { 'r' } else { '-' },
+            if self.write { 'w' } else { '-' },
+            if self.execute { 'x' } else { '-' },
+            if self.shared { 's' } else { 'p' }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Heap,
+    Stack,
+    /// backed by a file on disk (executable, shared library, mapped file)
+    Module,
+    Anonymous,
+    /// kernel provided mappings such as [vdso], [vvar] and [vsyscall]
+    Special,
+}
+
+impl RegionKind {
+    fn classify(path: Option<&str>) -> Self {
+        match path {
+            None => RegionKind::Anonymous,
+            Some("[heap]") => RegionKind::Heap,
+            Some(p) if p.starts_with("[stack") => RegionKind::Stack,
+            Some(p) if p.starts_with('[') => RegionKind::Special,
+            Some(_) => RegionKind::Module,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryRegion {
+    pub start: u64,
+    pub end: u64,
+    pub permissions: MemoryPermissions,
+    pub offset: u64,
+    pub path: Option<String>,
+    pub kind: RegionKind,
+}
+
+impl MemoryRegion {
+    fn new(
+        start: u64,
+        end: u64,
+        permissions: MemoryPermissions,
+        offset: u64,
+        path: Option<String>,
+    ) -> Self {
+        let kind = RegionKind::classify(path.as_deref());
+        Self {
+            start,
+            end,
+            permissions,
+            offset,
+            path,
+            kind,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        address >= self.start && address < self.end
+    }
+}
+
+impl fmt::Display for MemoryRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "0x{:x}-0x{:x} {} {:?}",
+            self.start, self.end, self.permissions, self.kind
+        )?;
+        if let Some(path) = &self.path {
+            write!(f, " {}", path)?;
+        }
+        Ok(())
+    }
+}
+
+/// A contiguous block of readable target memory
+#[derive(Debug, Clone)]
+pub struct MemorySpan {
+    pub address: u64,
+    pub data: Vec<u8>,
+}
+
+impl MemorySpan {
+    pub fn end(&self) -> u64 {
+        self.address + self.data.len() as u64
+    }
+}
+
+/// Result of a read that tolerates unreadable pages.
+///
+/// `spans` are sorted and non overlapping, anything between them is a hole.
+#[derive(Debug, Clone)]
+pub struct PartialRead {
+    pub address: u64,
+    pub size: usize,
+    pub spans: Vec<MemorySpan>,
+}
+
+impl PartialRead {
+    fn push(&mut self, address: u64, data: Vec<u8>) {
+        if data.is_empty() {
+            return;
+        }
+        if let Some(last) = self.spans.last_mut() {
+            if last.end() == address {
+                last.data.extend_from_slice(&data);
+                return;
+            }
+        }
+        self.spans.push(MemorySpan { address, data });
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.bytes_read() == self.size
+    }
+
+    pub fn bytes_read(&self) -> usize {
+        self.spans.iter().map(|s| s.data.len()).sum()
+    }
+
+    /// Unreadable ranges as (start, end)
+    pub fn holes(&self) -> Vec<(u64, u64)> {
+        let mut holes = Vec::new();
+        let mut cursor = self.address;
+        for span in &self.spans {
+            if span.address > cursor {
+                holes.push((cursor, span.address));
+            }
+            cursor = span.end();
+        }
+        let end = self.address.saturating_add(self.size as u64);
+        if cursor < end {
+            holes.push((cursor, end));
+        }
+        holes
+    }
+
+    /// Flatten into a single buffer, holes are filled with `fill`
+    pub fn to_vec_filled(&self, fill: u8) -> Vec<u8> {
+        let mut buf = vec![fill; self.size];
+        for span in &self.spans {
+            let start = (span.address - self.address) as usize;
+            buf[start..start + span.data.len()].copy_from_slice(&span.data);
+        }
+        buf
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16).ok()
+}
+
+/// Parse the contents of /proc/<pid>/maps
+pub fn parse_proc_maps(maps: &str) -> Vec<MemoryRegion> {
+    let mut regions = Vec::new();
+    for line in maps.lines() {
+        // start-end perms offset dev inode [path]
+        let mut fields = line.splitn(6, char::is_whitespace);
+        let (Some(range), Some(perms), Some(offset), Some(_dev), Some(_inode)) = (
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+        ) else {
+            continue;
+        };
+        let Some((start, end)) = range.split_once('-') else {
+            continue;
+        };
+        let (Some(start), Some(end), Some(perms), Some(offset)) = (
+            parse_hex(start),
+            parse_hex(end),
+            MemoryPermissions::parse(perms),
+            parse_hex(offset),
+        ) else {
+            continue;
+        };
+        let path = fields
+            .next()
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(str::to_owned);
+        regions.push(MemoryRegion::new(start, end, perms, offset, path));
+    }
+    regions
+}
+
+/// Parse the output of lldb's `memory region --all`
+///
+///   [0x0000555555554000-0x0000555555555000) r-- /path/to/bin PT_LOAD[0]
+pub fn parse_lldb_regions(output: &str) -> Vec<MemoryRegion> {
+    let mut regions = Vec::new();
+    for line in output.lines() {
+        let line = line.trim();
+        let Some(rest) = line.strip_prefix('[') else {
+            continue;
+        };
+        let Some((range, rest)) = rest.split_once(')') else {
+            continue;
+        };
+        let Some((start, end)) = range.split_once('-') else {
+            continue;
+        };
+        let (perms, path) = rest
+            .trim_start()
+            .split_once(' ')
+            .unwrap_or((rest.trim(), ""));
+        let (Some(start), Some(end), Some(perms)) = (
+            parse_hex(start),
+            parse_hex(end),
+            MemoryPermissions::parse(perms),
+        ) else {
+            continue;
+        };
+        // the path may contain spaces, the segment name after it does not
+        let path = path.trim();
+        let path = match path.rsplit_once(' ') {
+            Some((file, segment)) if segment.starts_with("PT_") || segment.starts_with("__") => {
+                file.trim_end()
+            }
+            _ => path,
+        };
+        let path = (!path.is_empty()).then(|| path.to_owned());
+        // lldb also reports the unmapped gaps between regions
+        if perms.is_none() && path.is_none() {
+            continue;
+        }
+        regions.push(MemoryRegion::new(start, end, perms, 0, path));
+    }
+    regions
+}
+
+/// Parse the output of gdb's `info proc mappings`
+///
+///   0x555555554000     0x555555555000     0x1000        0x0  r--p   /path/to/bin
+pub fn parse_gdb_mappings(output: &str) -> Vec<MemoryRegion> {
+    let mut regions = Vec::new();
+    for line in output.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 || !fields[0].starts_with("0x") {
+            continue;
+        }
+        let (Some(start), Some(end), Some(offset)) = (
+            parse_hex(fields[0]),
+            parse_hex(fields[1]),
+            parse_hex(fields[3]),
+        ) else {
+            continue;
+        };
+        // older gdb versions have no Perms column
+        let (perms, path_idx) = match fields.get(4).and_then(|p| MemoryPermissions::parse(p)) {
+            Some(p) => (p, 5),
+            None => (
+                MemoryPermissions {
+                    read: true,
+                    ..Default::default()
+                },
+                4,
+            ),
+        };
+        let path = if fields.len() > path_idx {
+            Some(fields[path_idx..].join(" "))
+        } else {
+            None
+        };
+        regions.push(MemoryRegion::new(start, end, perms, offset, path));
+    }
+    regions
+}
+
+// bisect on page boundaries until the unreadable pages are isolated
+fn read_range_into(
+    read: &impl Fn(u64, usize) -> Option<Vec<u8>>,
+    result: &mut PartialRead,
+    start: u64,
+    end: u64,
+) {
+    if start >= end {
+        return;
+    }
+    let size = (end - start) as usize;
+    if let Some(mut data) = read(start, size).filter(|data| !data.is_empty()) {
+        data.truncate(size);
+        // the core returns what it could read, the rest may still be partly readable
+        let read_end = start + data.len() as u64;
+        result.push(start, data);
+        read_range_into(read, result, read_end, end);
+        return;
+    }
+
+    let first_page = start / PAGE_SIZE;
+    let last_page = (end - 1) / PAGE_SIZE;
+    if first_page == last_page {
+        return;
+    }
+    let mid = (first_page + (last_page - first_page).div_ceil(2)) * PAGE_SIZE;
+    read_range_into(read, result, start, mid);
+    read_range_into(read, result, mid, end);
+}
+
+impl DebuggerController {
+    /// Check whether the target process lives on this machine, i.e. its
+    /// /proc entry is visible and belongs to the executable being debugged.
+    pub fn is_local_target(&self) -> bool {
+        let pid = self.active_pid();
+        if pid == 0 {
+            return false;
+        }
+        let Ok(exe) = std::fs::read_link(format!("/proc/{}/exe", pid)) else {
+            return false;
+        };
+        let expected = self.executable_path();
+        if expected.is_empty() {
+            return true;
+        }
+        exe.file_name() == Path::new(&expected).file_name()
+    }
+
+    /// Get the memory map of the target.
+    ///
+    /// Local targets are read from /proc/<pid>/maps, remote targets fall back
+    /// to asking the backend (lldb `memory region --all` or gdb `info proc mappings`).
+    pub fn memory_regions(&self) -> Vec<MemoryRegion> {
+        if self.is_local_target() {
+            if let Ok(maps) = std::fs::read_to_string(format!("/proc/{}/maps", self.active_pid())) {
+                return parse_proc_maps(&maps);
+            }
+        }
+
+        if self.adapter_type().contains("GDB") {
+            let regions = parse_gdb_mappings(&self.invoke_backend_command("info proc mappings"));
+            if !regions.is_empty() {
+                return regions;
+            }
+        }
+        parse_lldb_regions(&self.invoke_backend_command("memory region --all"))
+    }
+
+    /// Find the region containing an address
+    pub fn region_containing(&self, address: u64) -> Option<MemoryRegion> {
+        self.memory_regions()
+            .into_iter()
+            .find(|r| r.contains(address))
+    }
+
+    /// Read memory, keeping whatever is readable.
+    ///
+    /// Unlike `read_memory`, an unreadable page only leaves a hole in the
+    /// result instead of failing the whole request.
+    pub fn read_memory_partial(&self, address: u64, size: usize) -> PartialRead {
+        let mut result = PartialRead {
+            address,
+            size,
+            spans: Vec::new(),
+        };
+        read_range_into(
+            &|address, size| self.read_memory(address, size),
+            &mut result,
+            address,
+            address.saturating_add(size as u64),
+        );
+        result
+    }
+
+    /// Read a large range in parallel, page-aligned chunks of `PARALLEL_CHUNK_SIZE`.
+    ///
+    /// Meant for dumping modules or scanning the heap; unreadable pages are left as holes.
+    pub fn read_memory_parallel(&self, address: u64, size: usize) -> PartialRead {
+        let end = address.saturating_add(size as u64);
+        let mut bounds = Vec::new();
+        let mut chunk_start = address;
+        while chunk_start < end {
+            let chunk_end =
+                ((chunk_start / PARALLEL_CHUNK_SIZE) + 1).saturating_mul(PARALLEL_CHUNK_SIZE);
+            let chunk_end = chunk_end.min(end);
+            bounds.push((chunk_start, chunk_end));
+            chunk_start = chunk_end;
+        }
+
+        let chunks: Vec<PartialRead> = bounds
+            .par_iter()
+            .map(|&(start, end)| self.read_memory_partial(start, (end - start) as usize))
+            .collect();
+
+        let mut result = PartialRead {
+            address,
+            size,
+            spans: Vec::new(),
+        };
+        for chunk in chunks {
+            for span in chunk.spans {
+                result.push(span.address, span.data);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_maps() {
+        let maps = "\
+555555554000-555555556000 r--p 00000000 fd:01 1234                       /usr/bin/cat
+555555556000-55555555a000 r-xp 00002000 fd:01 1234                       /usr/bin/cat
+55555555e000-55555557f000 rw-p 00000000 00:00 0                          [heap]
+7ffff7d00000-7ffff7d10000 rw-p 00000000 00:00 0
+7ffffffde000-7ffffffff000 rw-p 00000000 00:00 0                          [stack]
+7ffff7fc1000-7ffff7fc5000 r--p 00000000 00:00 0                          [vvar]
+";
+        let regions = parse_proc_maps(maps);
+        assert_eq!(regions.len(), 6);
+        assert_eq!(regions[1].start, 0x555555556000);
+        assert_eq!(regions[1].offset, 0x2000);
+        assert!(regions[1].permissions.execute);
+        assert_eq!(regions[1].kind, RegionKind::Module);
+        assert_eq!(regions[2].kind, RegionKind::Heap);
+        assert_eq!(regions[3].kind, RegionKind::Anonymous);
+        assert_eq!(regions[4].kind, RegionKind::Stack);
+        assert_eq!(regions[5].kind, RegionKind::Special);
+    }
+
+    #[test]
+    fn test_parse_lldb_regions() {
+        let out = "\
+[0x0000000000000000-0x0000555555554000) ---
+[0x0000555555554000-0x0000555555556000) r-- /usr/bin/cat PT_LOAD[0]
+[0x0000555555556000-0x0000555555557000) r-x /opt/my tools/bin PT_LOAD[1]
+[0x00007ffffffde000-0x00007ffffffff000) rw- [stack]
+";
+        let regions = parse_lldb_regions(out);
+        assert_eq!(regions.len(), 3);
+        assert_eq!(regions[0].path.as_deref(), Some("/usr/bin/cat"));
+        assert_eq!(regions[1].path.as_deref(), Some("/opt/my tools/bin"));
+        assert!(regions[1].permissions.execute);
+        assert_eq!(regions[2].kind, RegionKind::Stack);
+    }
+
+    #[test]
+    fn test_parse_gdb_mappings() {
+        let out = "\
+process 1234
+Mapped address spaces:
+
+          Start Addr           End Addr       Size     Offset  Perms  objfile
+      0x555555554000     0x555555556000     0x2000        0x0  r--p   /usr/bin/cat
+      0x555555556000     0x55555555a000     0x4000     0x2000  r-xp   /opt/my tools/bin
+      0x7ffffffde000     0x7ffffffff000    0x21000        0x0  rw-p   [stack]
+      0x7ffff7d00000     0x7ffff7d10000    0x10000        0x0  rw-p
+";
+        let regions = parse_gdb_mappings(out);
+        assert_eq!(regions.len(), 4);
+        assert_eq!(regions[1].offset, 0x2000);
+        assert!(regions[1].permissions.execute);
+        assert_eq!(regions[1].path.as_deref(), Some("/opt/my tools/bin"));
+        assert_eq!(regions[2].kind, RegionKind::Stack);
+        assert_eq!(regions[3].path, None);
+
+        // gdb before 12 prints no Perms column
+        let old = "      0x400000           0x401000     0x1000        0x0 /bin/true\n";
+        let regions = parse_gdb_mappings(old);
+        assert_eq!(regions.len(), 1);
+        assert!(regions[0].permissions.read);
+        assert_eq!(regions[0].path.as_deref(), Some("/bin/true"));
+    }
+
+    /// Reader over `memory` at 0x10000 that, like the core, returns the bytes
+    /// up to the first `bad` page and fails when the access starts in one
+    fn fake_reader<'a>(
+        memory: &'a [u8],
+        bad: &'a [u64],
+    ) -> impl Fn(u64, usize) -> Option<Vec<u8>> + 'a {
+        move |address, size| {
+            let start = address.checked_sub(0x10000)? as usize;
+            let data = memory.get(start..start + size)?;
+            let pages = address / PAGE_SIZE..=(address + size as u64 - 1) / PAGE_SIZE;
+            match bad
+                .iter()
+                .copied()
+                .filter(|page| pages.contains(page))
+                .min()
+            {
+                Some(page) if page == address / PAGE_SIZE => None,
+                Some(page) => Some(data[..(page * PAGE_SIZE - address) as usize].to_vec()),
+                None => Some(data.to_vec()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_partial_read_bisect() {
+        let memory: Vec<u8> = (0..8 * PAGE_SIZE).map(|i| i as u8).collect();
+        let base = 0x10000u64;
+        let bad = [base / PAGE_SIZE + 2, base / PAGE_SIZE + 5];
+        let read = fake_reader(&memory, &bad);
+
+        let (start, size) = (base + 0x800, 7 * PAGE_SIZE as usize);
+        let mut result = PartialRead {
+            address: start,
+            size,
+            spans: Vec::new(),
+        };
+        read_range_into(&read, &mut result, start, start + size as u64);
+
+        assert!(!result.is_complete());
+        assert_eq!(result.bytes_read(), size - 2 * PAGE_SIZE as usize);
+        assert_eq!(
+            result.holes(),
+            [
+                (base + 2 * PAGE_SIZE, base + 3 * PAGE_SIZE),
+                (base + 5 * PAGE_SIZE, base + 6 * PAGE_SIZE),
+            ]
+        );
+        // contiguous reads are merged into one span per readable run
+        assert_eq!(result.spans.len(), 3);
+
+        let filled = result.to_vec_filled(0xcc);
+        assert_eq!(filled.len(), size);
+        assert_eq!(filled[0], memory[0x800]);
+        let hole = (2 * PAGE_SIZE - 0x800) as usize;
+        assert!(filled[hole..hole + PAGE_SIZE as usize]
+            .iter()
+            .all(|&b| b == 0xcc));
+        assert_eq!(
+            filled[hole + PAGE_SIZE as usize],
+            memory[3 * PAGE_SIZE as usize]
+        );
+    }
+
+    #[test]
+    fn test_partial_read_holes() {
+        let mut result = PartialRead {
+            address: 0x1000,
+            size: 0x100,
+            spans: Vec::new(),
+        };
+        assert_eq!(result.holes(), [(0x1000, 0x1100)]);
+        assert_eq!(result.to_vec_filled(0), vec![0; 0x100]);
+
+        result.push(0x1010, vec![1; 0x10]);
+        result.push(0x1020, vec![2; 0x10]);
+        result.push(0x10f0, vec![3; 0x10]);
+        assert_eq!(result.spans.len(), 2);
+        assert_eq!(result.holes(), [(0x1000, 0x1010), (0x1030, 0x10f0)]);
+        let filled = result.to_vec_filled(0xff);
+        assert_eq!(&filled[0x0e..0x12], &[0xff, 0xff, 1, 1]);
+        assert_eq!(filled[0x20], 2);
+        assert_eq!(filled[0xff], 3);
+
+        // near the top of the address space the end saturates
+        let top = PartialRead {
+            address: u64::MAX - 0xf,
+            size: 0x20,
+            spans: Vec::new(),
+        };
+        assert_eq!(top.holes(), [(u64::MAX - 0xf, u64::MAX)]);
+    }
+}
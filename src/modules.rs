@@ -0,0 +1,666 @@
+// module load/unload notifications and analysis views of loaded modules
+
+use crate::elf::{
+    dynamic_entries, gnu_build_id, link_base, DT_DEBUG, DT_SONAME, DT_STRTAB, PF_R, PF_W, PF_X,
+    PT_DYNAMIC, PT_LOAD, PT_NOTE,
+};
+use crate::memory::MemoryPermissions;
+use crate::types::CoreView;
+use crate::{
+    BNDebugStopReason, BNDebuggerEventType, DebugModule, DebuggerController, WeakController,
+};
+use binaryninja::binary_view::BinaryView;
+use binaryninjacore_sys as sys;
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+
+type ModuleCallback = Arc<dyn Fn(&DebugModule) + Send + Sync>;
+
+#[derive(Default)]
+struct WatcherState {
+    modules: Vec<DebugModule>,
+    hook_loader: bool,
+    /// Address of the armed loader breakpoint
+    rendezvous: Option<u64>,
+    /// The breakpoint was added by a watcher, not by the user
+    owns_rendezvous: bool,
+    on_loaded: Vec<ModuleCallback>,
+    on_unloaded: Vec<ModuleCallback>,
+}
+
+fn same_module(a: &DebugModule, b: &DebugModule) -> bool {
+    a.address == b.address && a.name == b.name
+}
+
+/// Reports modules appearing in and disappearing from the target, see
+/// `DebuggerController::watch_modules`
+pub struct ModuleWatcher {
+    dbg: WeakController,
+    callback: usize,
+    state: Arc<Mutex<WatcherState>>,
+}
+
+impl ModuleWatcher {
+    /// Called with every module that appeared since the previous stop
+    pub fn on_loaded<F>(&self, callback: F)
+    where
+        F: Fn(&DebugModule) + Send + Sync + 'static,
+    {
+        self.state
+            .lock()
+            .unwrap()
+            .on_loaded
+            .push(Arc::new(callback));
+    }
+
+    /// Called with every module that disappeared since the previous stop
+    pub fn on_unloaded<F>(&self, callback: F)
+    where
+        F: Fn(&DebugModule) + Send + Sync + 'static,
+    {
+        self.state
+            .lock()
+            .unwrap()
+            .on_unloaded
+            .push(Arc::new(callback));
+    }
+
+    /// Modules as of the last refresh
+    pub fn modules(&self) -> Vec<DebugModule> {
+        self.state.lock().unwrap().modules.clone()
+    }
+
+    /// Stop at the dynamic loader's rendezvous breakpoint (`r_debug.r_brk`,
+    /// i.e. `_dl_debug_state`) on every library load and unload.
+    ///
+    /// Callbacks then run while a new library is mapped but before its
+    /// initializers. `go_and_wait` and `run_to_and_wait` run them and resume
+    /// past these stops themselves; other calls report them as a breakpoint
+    /// stop. A breakpoint the user already had at that address is used as is
+    /// and always stops. The breakpoint is armed as soon as the loader has
+    /// filled in `DT_DEBUG`, static executables have none.
+    pub fn set_loader_hook(&self, enabled: bool) {
+        let Some(dbg) = self.dbg.upgrade() else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap();
+        state.hook_loader = enabled;
+        if enabled {
+            if dbg.is_connected() && !dbg.is_running() {
+                arm_loader_hook(&dbg, &self.state, &mut state);
+            }
+        } else {
+            disarm_loader_hook(&dbg, &self.state, &mut state);
+        }
+    }
+
+    /// Address of the armed loader breakpoint
+    pub fn loader_breakpoint(&self) -> Option<u64> {
+        self.state.lock().unwrap().rendezvous
+    }
+
+    /// Re-read the module list now instead of waiting for the next stop
+    pub fn refresh(&self) {
+        if let Some(dbg) = self.dbg.upgrade() {
+            refresh(&dbg, &self.state);
+        }
+    }
+}
+
+impl Drop for ModuleWatcher {
+    fn drop(&mut self) {
+        if let Some(dbg) = self.dbg.upgrade() {
+            dbg.remove_event_callback(self.callback);
+            disarm_loader_hook(&dbg, &self.state, &mut self.state.lock().unwrap());
+        }
+    }
+}
+
+fn arm_loader_hook(
+    dbg: &DebuggerController,
+    watcher: &Arc<Mutex<WatcherState>>,
+    state: &mut WatcherState,
+) {
+    if state.rendezvous.is_some() {
+        return;
+    }
+    let Some(address) = dbg.loader_rendezvous() else {
+        return;
+    };
+    let mut hooks = dbg.inner.loader_hooks.hooks.lock().unwrap();
+    let shared = hooks.iter().any(|hook| hook.address == address);
+    state.owns_rendezvous = shared || !dbg.contains_breakpoint(address);
+    if state.owns_rendezvous {
+        if !shared {
+            dbg.add_breakpoint(address);
+        }
+        hooks.push(LoaderHook {
+            address,
+            watcher: Arc::downgrade(watcher),
+        });
+    }
+    state.rendezvous = Some(address);
+}
+
+/// Remove the watcher's loader breakpoint if it created it and no other
+/// watcher uses it
+fn disarm_loader_hook(
+    dbg: &DebuggerController,
+    watcher: &Arc<Mutex<WatcherState>>,
+    state: &mut WatcherState,
+) {
+    let Some(address) = state.rendezvous.take() else {
+        return;
+    };
+    if !std::mem::take(&mut state.owns_rendezvous) {
+        return;
+    }
+    let watcher = Arc::downgrade(watcher);
+    let mut hooks = dbg.inner.loader_hooks.hooks.lock().unwrap();
+    hooks.retain(|hook| !(hook.address == address && Weak::ptr_eq(&hook.watcher, &watcher)));
+    if !hooks.iter().any(|hook| hook.address == address) {
+        dbg.delete_breakpoint(address);
+    }
+}
+
+struct LoaderHook {
+    address: u64,
+    watcher: Weak<Mutex<WatcherState>>,
+}
+
+/// Loader breakpoints created by module watchers
+#[derive(Default)]
+pub(crate) struct LoaderHooks {
+    hooks: Mutex<Vec<LoaderHook>>,
+}
+
+/// Diff the module list against the last one and run the callbacks
+fn refresh(dbg: &DebuggerController, state: &Mutex<WatcherState>) {
+    update(state, dbg.modules());
+}
+
+fn update(state: &Mutex<WatcherState>, modules: Vec<DebugModule>) {
+    let (loaded, unloaded, on_loaded, on_unloaded) = {
+        let mut state = state.lock().unwrap();
+        let loaded: Vec<DebugModule> = modules
+            .iter()
+            .filter(|m| !state.modules.iter().any(|old| same_module(old, m)))
+            .cloned()
+            .collect();
+        let unloaded: Vec<DebugModule> = state
+            .modules
+            .iter()
+            .filter(|old| !modules.iter().any(|m| same_module(old, m)))
+            .cloned()
+            .collect();
+        state.modules = modules;
+        (
+            loaded,
+            unloaded,
+            state.on_loaded.clone(),
+            state.on_unloaded.clone(),
+        )
+    };
+    // outside the lock, callbacks may use the watcher
+    for module in &unloaded {
+        for callback in &on_unloaded {
+            callback(module);
+        }
+    }
+    for module in &loaded {
+        for callback in &on_loaded {
+            callback(module);
+        }
+    }
+}
+
+impl DebuggerController {
+    /// Start watching for loaded and unloaded modules.
+    ///
+    /// The module list is diffed on every stop, or on every library load when
+    /// `ModuleWatcher::set_loader_hook` is enabled. Modules present when the
+    /// watcher is created are not reported, those of a newly launched process
+    /// are. Watching stops when the watcher is dropped.
+    pub fn watch_modules(&self) -> ModuleWatcher {
+        let state = Arc::new(Mutex::new(WatcherState::default()));
+        if self.is_connected() {
+            state.lock().unwrap().modules = self.modules();
+        }
+
+        let dbg = self.downgrade();
+        let events = Arc::clone(&state);
+        let callback = self.register_event_callback("rust-module-watcher", move |event| {
+            use BNDebuggerEventType::*;
+            let Some(dbg) = dbg.upgrade() else {
+                return;
+            };
+            match event.event_type {
+                TargetStoppedEventType => {}
+                TargetExitedEventType | DetachedEventType => {
+                    // the next process loads at different addresses
+                    disarm_loader_hook(&dbg, &events, &mut events.lock().unwrap());
+                    update(&events, Vec::new());
+                    return;
+                }
+                _ => return,
+            }
+
+            refresh(&dbg, &events);
+            let mut state = events.lock().unwrap();
+            if state.hook_loader {
+                arm_loader_hook(&dbg, &events, &mut state);
+            }
+        });
+
+        ModuleWatcher {
+            dbg: self.downgrade(),
+            callback,
+            state,
+        }
+    }
+
+    /// Report module changes to the watchers whose loader breakpoint the
+    /// target stopped at. Returns false if the stop was not at one, it is
+    /// then the caller's to handle.
+    ///
+    /// Called by the blocking calls, which resume past these stops; the
+    /// event callback may not have run yet when they return.
+    pub(crate) fn handle_loader_stop(&self, reason: BNDebugStopReason) -> bool {
+        if reason != BNDebugStopReason::Breakpoint {
+            return false;
+        }
+        let ip = self.ip();
+        let watchers: Vec<Arc<Mutex<WatcherState>>> = {
+            let hooks = self.inner.loader_hooks.hooks.lock().unwrap();
+            hooks
+                .iter()
+                .filter(|hook| hook.address == ip)
+                .filter_map(|hook| hook.watcher.upgrade())
+                .collect()
+        };
+        for watcher in &watchers {
+            refresh(self, watcher);
+        }
+        !watchers.is_empty()
+    }
+
+    /// The module of the main executable
+    pub fn main_module(&self) -> Option<DebugModule> {
+        self.main_module_of(&self.modules())
+    }
+
+    /// The main executable among `modules`, a module list read earlier
+    pub(crate) fn main_module_of(&self, modules: &[DebugModule]) -> Option<DebugModule> {
+        let executable = self.executable_path();
+        let name = Path::new(&executable).file_name();
+        modules
+            .iter()
+            .find(|m| m.name == executable)
+            .or_else(|| {
+                modules
+                    .iter()
+                    .find(|m| name.is_some() && Path::new(&m.name).file_name() == name)
+            })
+            .or(modules.first())
+            .cloned()
+    }
+
+    /// `r_debug.r_brk` of the dynamic loader, found through the main
+    /// executable's DT_DEBUG entry once the loader has initialized it
+    pub(crate) fn loader_rendezvous(&self) -> Option<u64> {
+        let main = self.main_module()?;
+        let (header, headers) = self.elf_headers(main.address)?;
+        let bias = main.address.wrapping_sub(link_base(&headers)?);
+        let dynamic = headers.iter().find(|ph| ph.p_type == PT_DYNAMIC)?;
+        let data = self.read_memory(dynamic.vaddr.wrapping_add(bias), dynamic.memsz as usize)?;
+        let (_, r_debug) = dynamic_entries(&data, header.is_64)
+            .into_iter()
+            .find(|&(tag, _)| tag == DT_DEBUG)?;
+        if r_debug == 0 {
+            return None;
+        }
+        // struct r_debug { int r_version; struct link_map *r_map; ElfW(Addr) r_brk; ... }
+        let slot = self.address_size() as u64;
+        let r_brk = self.read_pointer(r_debug + 2 * slot)?;
+        (r_brk != 0).then_some(r_brk)
+    }
+}
+
+/// A module `module_view` opened a view for
+struct OpenedView {
+    name: String,
+    size: u64,
+    /// None records a module whose file could not be opened
+    view: Option<CoreView>,
+}
+
+/// Analysis views opened by `module_view`, by module base
+#[derive(Default)]
+pub(crate) struct ModuleViews {
+    views: Mutex<BTreeMap<u64, OpenedView>>,
+}
+
+impl DebuggerController {
+    /// Analysis view of a loaded module.
+    ///
+    /// The main executable gets the live view. Other modules have their file
+    /// on disk opened in the running core session, rebased to the module's
+    /// runtime address and analyzed, which can take a while for large
+    /// libraries. Views are cached per module path and base, and once opened
+    /// are used by `backtrace`, `unwind`, `function_args` and friends for
+    /// addresses inside the module. Views of modules that are no longer loaded
+    /// are released on the next call, or with `close_module_views`.
+    pub fn module_view(&self, module: &DebugModule) -> Option<binaryninja::rc::Ref<BinaryView>> {
+        let view = self.module_core_view(module)?;
+        // Ref takes its own reference, `view` releases ours
+        let bv: BinaryView = unsafe { std::mem::transmute(view.handle()) };
+        Some(bv.to_owned())
+    }
+
+    /// Release every view opened by `module_view`
+    pub fn close_module_views(&self) {
+        self.inner.module_views.views.lock().unwrap().clear();
+        self.inner.symbols.invalidate();
+    }
+
+    pub(crate) fn module_core_view(&self, module: &DebugModule) -> Option<CoreView> {
+        let modules = self.modules();
+        if self
+            .main_module_of(&modules)
+            .is_some_and(|main| main.address == module.address)
+        {
+            return self.live_view();
+        }
+        self.prune_module_views(&modules);
+        if let Some(opened) = self.opened_module_entry(module) {
+            return opened;
+        }
+
+        // analysis can take minutes, other lookups must not wait for it
+        let view = open_rebased(module);
+        if view.is_none() {
+            log::warn!("cannot open {} for analysis", module.name);
+        }
+        let view = {
+            let mut views = self.inner.module_views.views.lock().unwrap();
+            let opened = views.entry(module.address).or_insert(OpenedView {
+                name: module.name.clone(),
+                size: module.size as u64,
+                view,
+            });
+            opened.view.clone()
+        };
+        // symbols now come from the view's analysis
+        self.inner.symbols.invalidate();
+        view
+    }
+
+    /// Some(view or None) if `module_view` was called for the module
+    fn opened_module_entry(&self, module: &DebugModule) -> Option<Option<CoreView>> {
+        let views = self.inner.module_views.views.lock().unwrap();
+        views
+            .get(&module.address)
+            .filter(|opened| opened.name == module.name)
+            .map(|opened| opened.view.clone())
+    }
+
+    /// The view `module_view` already opened for a module, without opening one
+    pub(crate) fn opened_module_view(&self, module: &DebugModule) -> Option<CoreView> {
+        self.opened_module_entry(module).flatten()
+    }
+
+    /// Release the views of modules missing from `modules`, i.e. unloaded
+    pub(crate) fn prune_module_views(&self, modules: &[DebugModule]) {
+        let mut views = self.inner.module_views.views.lock().unwrap();
+        views.retain(|&base, opened| {
+            modules
+                .iter()
+                .any(|m| m.address == base && m.name == opened.name)
+        });
+    }
+
+    /// View with the analysis of the code at `address`: the view of the
+    /// containing module if one was opened with `module_view`, else the live view
+    pub(crate) fn analysis_view(&self, address: u64) -> Option<CoreView> {
+        {
+            let views = self.inner.module_views.views.lock().unwrap();
+            if let Some((base, opened)) = views.range(..=address).next_back() {
+                if address - base < opened.size {
+                    if let Some(view) = &opened.view {
+                        return Some(view.clone());
+                    }
+                }
+            }
+        }
+        self.live_view()
+    }
+}
+
+fn open_rebased(module: &DebugModule) -> Option<CoreView> {
+    if !Path::new(&module.name).is_file() {
+        return None;
+    }
+    let path = CString::new(module.name.as_str()).ok()?;
+    let options = serde_json::json!({ "loader.imageBase": module.address }).to_string();
+    let options = CString::new(options).ok()?;
+    unsafe {
+        CoreView::from_raw(sys::BNLoadFilename(
+            path.as_ptr(),
+            true,
+            options.as_ptr(),
+            None,
+            std::ptr::null_mut(),
+        ))
+    }
+}
+
+/// Where separate debug files are looked up by build-id
+pub const DEBUG_FILE_DIRECTORY: &str = "/usr/lib/debug/.build-id";
+
+/// A PT_LOAD segment of a module at its runtime address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModuleSegment {
+    pub address: u64,
+    pub size: u64,
+    pub file_offset: u64,
+    pub file_size: u64,
+    pub permissions: MemoryPermissions,
+}
+
+impl fmt::Display for ModuleSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "0x{:x}-0x{:x} {} file 0x{:x}+0x{:x}",
+            self.address,
+            self.address + self.size,
+            self.permissions,
+            self.file_offset,
+            self.file_size
+        )
+    }
+}
+
+/// Whether a file on disk is the image loaded in the target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileMatch {
+    /// Both have the same build-id
+    Matches,
+    /// The build-ids differ, the file was rebuilt or replaced
+    Differs,
+    /// A build-id is missing or the file cannot be read
+    Unverified,
+}
+
+impl FileMatch {
+    fn compare(loaded: Option<&[u8]>, path: &Path) -> Self {
+        match (loaded, file_build_id(path)) {
+            (Some(loaded), Some(file)) if loaded == file.as_slice() => Self::Matches,
+            (Some(_), Some(_)) => Self::Differs,
+            _ => Self::Unverified,
+        }
+    }
+}
+
+impl fmt::Display for FileMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Matches => "matches",
+            Self::Differs => "differs",
+            Self::Unverified => "unverified",
+        })
+    }
+}
+
+/// ELF metadata of a loaded module, read from the target's memory
+#[derive(Debug, Clone)]
+pub struct ModuleMetadata {
+    pub is_64: bool,
+    pub machine: u16,
+    /// Runtime entry point, None for libraries without one
+    pub entry: Option<u64>,
+    pub build_id: Option<Vec<u8>>,
+    pub segments: Vec<ModuleSegment>,
+    /// Runtime address of the PT_DYNAMIC segment, None for static executables
+    pub dynamic: Option<u64>,
+    pub soname: Option<String>,
+    /// The module's file on disk compared to the loaded image
+    pub file: FileMatch,
+    /// Separate debug file with the same build-id under `DEBUG_FILE_DIRECTORY`
+    pub debug_file: Option<PathBuf>,
+}
+
+impl ModuleMetadata {
+    /// Build-id as lowercase hex
+    pub fn build_id_hex(&self) -> Option<String> {
+        self.build_id.as_deref().map(hex)
+    }
+}
+
+impl fmt::Display for ModuleMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "build-id {} ({} file)",
+            self.build_id_hex().as_deref().unwrap_or("none"),
+            self.file
+        )?;
+        if let Some(soname) = &self.soname {
+            write!(f, " soname {}", soname)?;
+        }
+        if let Some(debug_file) = &self.debug_file {
+            write!(f, " debug {}", debug_file.display())?;
+        }
+        for segment in &self.segments {
+            write!(f, "\n    {}", segment)?;
+        }
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn file_build_id(path: &Path) -> Option<Vec<u8>> {
+    let mut file = std::fs::File::open(path).ok()?;
+    crate::elf::file_build_id(&mut file)
+}
+
+/// `DEBUG_FILE_DIRECTORY/ab/cdef...debug` for build-id `abcdef...`
+pub fn debug_file_path(build_id: &[u8]) -> Option<PathBuf> {
+    let (first, rest) = build_id.split_first()?;
+    Some(
+        Path::new(DEBUG_FILE_DIRECTORY)
+            .join(hex(&[*first]))
+            .join(format!("{}.debug", hex(rest))),
+    )
+}
+
+impl DebuggerController {
+    /// Parse the ELF header, program headers, build-id note and dynamic
+    /// section of a module from the target's memory, and match the module
+    /// against its file on disk and a separate debug file
+    pub fn module_metadata(&self, module: &DebugModule) -> Option<ModuleMetadata> {
+        let (header, headers) = self.elf_headers(module.address)?;
+        let bias = module.address.wrapping_sub(link_base(&headers)?);
+
+        let segments = headers
+            .iter()
+            .filter(|ph| ph.p_type == PT_LOAD)
+            .map(|ph| ModuleSegment {
+                address: ph.vaddr.wrapping_add(bias),
+                size: ph.memsz,
+                file_offset: ph.offset,
+                file_size: ph.filesz,
+                permissions: MemoryPermissions {
+                    read: ph.flags & PF_R != 0,
+                    write: ph.flags & PF_W != 0,
+                    execute: ph.flags & PF_X != 0,
+                    shared: false,
+                },
+            })
+            .collect();
+        let build_id = headers
+            .iter()
+            .filter(|ph| ph.p_type == PT_NOTE)
+            .find_map(|ph| {
+                let notes = self.read_memory(ph.vaddr.wrapping_add(bias), ph.memsz as usize)?;
+                gnu_build_id(&notes)
+            });
+        let dynamic = headers.iter().find(|ph| ph.p_type == PT_DYNAMIC);
+        let soname = dynamic.and_then(|ph| {
+            let data = self.read_memory(ph.vaddr.wrapping_add(bias), ph.memsz as usize)?;
+            self.soname(module, &dynamic_entries(&data, header.is_64), bias)
+        });
+        let debug_file = build_id
+            .as_deref()
+            .and_then(debug_file_path)
+            .filter(|path| FileMatch::compare(build_id.as_deref(), path) == FileMatch::Matches);
+
+        Some(ModuleMetadata {
+            is_64: header.is_64,
+            machine: header.machine,
+            entry: (header.entry != 0).then(|| header.entry.wrapping_add(bias)),
+            file: FileMatch::compare(build_id.as_deref(), Path::new(&module.name)),
+            build_id,
+            segments,
+            dynamic: dynamic.map(|ph| ph.vaddr.wrapping_add(bias)),
+            soname,
+            debug_file,
+        })
+    }
+
+    fn soname(&self, module: &DebugModule, entries: &[(u64, u64)], bias: u64) -> Option<String> {
+        let value = |tag| entries.iter().find(|&&(t, _)| t == tag).map(|&(_, v)| v);
+        let (strtab, offset) = (value(DT_STRTAB)?, value(DT_SONAME)?);
+        // the loader relocates DT_STRTAB in place on most architectures
+        let strtab = if strtab >= module.address && strtab - module.address < module.size as u64 {
+            strtab
+        } else {
+            strtab.wrapping_add(bias)
+        };
+        let data = self.read_memory(strtab.wrapping_add(offset), 256)?;
+        let name = data.split(|&b| b == 0).next()?;
+        (!name.is_empty()).then(|| String::from_utf8_lossy(name).into_owned())
+    }
+
+    /// Compare the file behind the live view with the main module loaded in
+    /// the target, to catch a stale analysis of a rebuilt or replaced binary
+    pub fn verify_analysed_file(&self) -> FileMatch {
+        let Some(build_id) = self
+            .main_module()
+            .and_then(|main| self.module_metadata(&main))
+            .and_then(|metadata| metadata.build_id)
+        else {
+            return FileMatch::Unverified;
+        };
+        match self.live_view().and_then(|view| view.original_filename()) {
+            Some(path) => FileMatch::compare(Some(&build_id), Path::new(&path)),
+            None => FileMatch::Unverified,
+        }
+    }
+}
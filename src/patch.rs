@@ -0,0 +1,380 @@
+// journal of live memory writes so they can be reverted
+
+use crate::DebuggerController;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PatchId(pub u64);
+
+impl fmt::Display for PatchId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Patch {
+    pub id: PatchId,
+    pub address: u64,
+    #[serde(with = "hex_bytes")]
+    pub original: Vec<u8>,
+    #[serde(with = "hex_bytes")]
+    pub patched: Vec<u8>,
+}
+
+impl Patch {
+    pub fn end(&self) -> u64 {
+        self.address + self.patched.len() as u64
+    }
+}
+
+impl fmt::Display for Patch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} @ 0x{:x}: {:02x?} -> {:02x?}",
+            self.id, self.address, self.original, self.patched
+        )
+    }
+}
+
+// bytes as a hex string, keeps exported journals readable
+mod hex_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], s: S) -> Result<S::Ok, S::Error> {
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        s.serialize_str(&hex)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(d)?;
+        if !hex.is_ascii() {
+            return Err(D::Error::custom("non-ASCII character in hex string"));
+        }
+        if hex.len() % 2 != 0 {
+            return Err(D::Error::custom("odd length hex string"));
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct JournalState {
+    enabled: bool,
+    restore_on_exit: bool,
+    next_id: u64,
+    // in application order
+    patches: Vec<Patch>,
+}
+
+#[derive(Default)]
+pub(crate) struct PatchJournal {
+    state: Mutex<JournalState>,
+}
+
+impl JournalState {
+    /// Memory writes that revert the patch at `idx`, as contiguous runs, and
+    /// the journal afterwards.
+    ///
+    /// Bytes that a later patch overwrote again are not written; the first
+    /// later patch covering them takes them over in its original instead.
+    fn revert_plan(&self, idx: usize) -> (Vec<(u64, Vec<u8>)>, Vec<Patch>) {
+        let patch = &self.patches[idx];
+        let mut later = self.patches[idx + 1..].to_vec();
+        let mut to_write: Vec<Option<u8>> = patch.original.iter().copied().map(Some).collect();
+        for other in &mut later {
+            let start = patch.address.max(other.address);
+            let end = patch.end().min(other.end());
+            for addr in start..end {
+                // only the first later patch to cover a byte read this patch's value
+                let Some(byte) = to_write[(addr - patch.address) as usize].take() else {
+                    continue;
+                };
+                other.original[(addr - other.address) as usize] = byte;
+            }
+        }
+
+        let mut runs = Vec::new();
+        let mut i = 0;
+        while i < to_write.len() {
+            if to_write[i].is_none() {
+                i += 1;
+                continue;
+            }
+            let run_start = i;
+            let mut run = Vec::new();
+            while let Some(Some(byte)) = to_write.get(i) {
+                run.push(*byte);
+                i += 1;
+            }
+            runs.push((patch.address + run_start as u64, run));
+        }
+
+        let mut patches = self.patches[..idx].to_vec();
+        patches.extend(later);
+        (runs, patches)
+    }
+}
+
+impl PatchJournal {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.state.lock().unwrap().enabled
+    }
+}
+
+impl DebuggerController {
+    /// Start recording the original bytes of every `write_memory` in the patch journal.
+    ///
+    /// Recorded patches are restored automatically before `detach()` and `quit()`
+    /// unless disabled with `set_restore_patches_on_exit(false)`.
+    pub fn enable_patch_journal(&self) {
+        let mut state = self.inner.patches.state.lock().unwrap();
+        state.enabled = true;
+        state.restore_on_exit = true;
+    }
+
+    /// Stop recording new writes, already recorded patches are kept
+    pub fn disable_patch_journal(&self) {
+        self.inner.patches.state.lock().unwrap().enabled = false;
+    }
+
+    pub fn set_restore_patches_on_exit(&self, restore: bool) {
+        self.inner.patches.state.lock().unwrap().restore_on_exit = restore;
+    }
+
+    /// Write memory and record the patch, regardless of whether the journal is enabled
+    pub fn patch_memory(&self, address: u64, data: &[u8]) -> Option<PatchId> {
+        let Some(original) = self
+            .read_memory_uncached(address, data.len())
+            .filter(|original| original.len() == data.len())
+        else {
+            log::warn!("cannot read original bytes at 0x{:x}", address);
+            return None;
+        };
+        if !self.write_memory_unjournaled(address, data) {
+            return None;
+        }
+
+        let mut state = self.inner.patches.state.lock().unwrap();
+        let id = PatchId(state.next_id);
+        state.next_id += 1;
+        state.patches.push(Patch {
+            id,
+            address,
+            original,
+            patched: data.to_vec(),
+        });
+        Some(id)
+    }
+
+    /// Recorded patches, oldest first
+    pub fn patches(&self) -> Vec<Patch> {
+        self.inner.patches.state.lock().unwrap().patches.clone()
+    }
+
+    /// Restore the bytes overwritten by a patch.
+    ///
+    /// Bytes that a later patch overwrote again keep the later patch's value,
+    /// and that patch will restore the pre-`id` bytes when it is reverted.
+    pub fn revert(&self, id: PatchId) -> bool {
+        let mut state = self.inner.patches.state.lock().unwrap();
+        let Some(idx) = state.patches.iter().position(|p| p.id == id) else {
+            return false;
+        };
+
+        let (runs, patches) = state.revert_plan(idx);
+        for (address, run) in runs {
+            if !self.write_memory_unjournaled(address, &run) {
+                return false;
+            }
+        }
+        state.patches = patches;
+        true
+    }
+
+    /// Revert every recorded patch, newest first.
+    ///
+    /// Patches that fail to revert stay in the journal.
+    pub fn revert_all(&self) -> bool {
+        let mut state = self.inner.patches.state.lock().unwrap();
+        let mut failed = Vec::new();
+        while let Some(patch) = state.patches.pop() {
+            if !self.write_memory_unjournaled(patch.address, &patch.original) {
+                log::warn!("failed to revert patch {}", patch);
+                failed.push(patch);
+            }
+        }
+        failed.reverse();
+        let ok = failed.is_empty();
+        state.patches = failed;
+        ok
+    }
+
+    /// Called before detach/quit, the journal does not outlive the process
+    pub(crate) fn restore_patches_on_exit(&self) {
+        let restore = {
+            let state = self.inner.patches.state.lock().unwrap();
+            state.restore_on_exit && !state.patches.is_empty()
+        };
+        if restore && self.is_connected() {
+            self.revert_all();
+        }
+        self.clear_patches();
+    }
+
+    /// Forget every recorded patch without writing anything. Called on
+    /// launch and restart, the original bytes belong to the old process
+    /// and its layout.
+    pub(crate) fn clear_patches(&self) {
+        self.inner.patches.state.lock().unwrap().patches.clear();
+    }
+
+    /// Serialize the journal to JSON
+    pub fn export_patches(&self) -> String {
+        let state = self.inner.patches.state.lock().unwrap();
+        serde_json::to_string_pretty(&state.patches).unwrap_or_default()
+    }
+
+    /// Apply the patches of an exported journal to the target, recording them.
+    ///
+    /// Returns the ids of the applied patches; patches that cannot be written are skipped.
+    pub fn import_patches(&self, json: &str) -> serde_json::Result<Vec<PatchId>> {
+        let patches: Vec<Patch> = serde_json::from_str(json)?;
+        let mut ids = Vec::new();
+        for patch in patches {
+            if let Some(current) = self.read_memory_uncached(patch.address, patch.original.len()) {
+                if current != patch.original {
+                    log::warn!(
+                        "bytes at 0x{:x} differ from the exported original, applying anyway",
+                        patch.address
+                    );
+                }
+            }
+            match self.patch_memory(patch.address, &patch.patched) {
+                Some(id) => ids.push(id),
+                None => log::warn!("failed to apply imported patch at 0x{:x}", patch.address),
+            }
+        }
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(id: u64, address: u64, original: &[u8], patched: &[u8]) -> Patch {
+        Patch {
+            id: PatchId(id),
+            address,
+            original: original.to_vec(),
+            patched: patched.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_patch_json_roundtrip() {
+        let patches = vec![
+            patch(0, 0x401000, &[0x55, 0x48], &[0xcc, 0x90]),
+            patch(3, 0x10, &[], &[]),
+        ];
+        let json = serde_json::to_string(&patches).unwrap();
+        assert!(json.contains("\"5548\""));
+        let parsed: Vec<Patch> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].id, PatchId(0));
+        assert_eq!(parsed[0].address, 0x401000);
+        assert_eq!(parsed[0].original, [0x55, 0x48]);
+        assert_eq!(parsed[0].patched, [0xcc, 0x90]);
+        assert!(parsed[1].patched.is_empty());
+
+        for bad in ["\"abc\"", "\"zz\"", "\"é\"", "\"aéa\""] {
+            let json = format!(
+                "[{{\"id\":0,\"address\":0,\"original\":{},\"patched\":\"00\"}}]",
+                bad
+            );
+            assert!(
+                serde_json::from_str::<Vec<Patch>>(&json).is_err(),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    /// Journal of `patches` (address, data) applied in order to `memory`
+    fn apply_patches(memory: &mut [u8], patches: &[(u64, [u8; 4])]) -> JournalState {
+        let mut state = JournalState::default();
+        for (id, &(address, data)) in patches.iter().enumerate() {
+            let range = address as usize..address as usize + data.len();
+            state
+                .patches
+                .push(patch(id as u64, address, &memory[range.clone()], &data));
+            memory[range].copy_from_slice(&data);
+        }
+        state
+    }
+
+    fn apply(memory: &mut [u8], runs: Vec<(u64, Vec<u8>)>) {
+        for (address, run) in runs {
+            let start = address as usize;
+            memory[start..start + run.len()].copy_from_slice(&run);
+        }
+    }
+
+    #[test]
+    fn test_revert_overlapping() {
+        let mut memory: Vec<u8> = (0..8).collect();
+        // 0..4 then 2..6, applied on top of each other
+        let mut state = apply_patches(&mut memory, &[(0, [0xaa; 4]), (2, [0xbb; 4])]);
+        assert_eq!(memory, [0xaa, 0xaa, 0xbb, 0xbb, 0xbb, 0xbb, 6, 7]);
+
+        // the older patch only restores what the newer one does not cover
+        let (runs, patches) = state.revert_plan(0);
+        assert_eq!(runs, [(0, vec![0, 1])]);
+        apply(&mut memory, runs);
+        assert_eq!(memory, [0, 1, 0xbb, 0xbb, 0xbb, 0xbb, 6, 7]);
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].original, [2, 3, 4, 5]);
+        state.patches = patches;
+
+        let (runs, patches) = state.revert_plan(0);
+        apply(&mut memory, runs);
+        assert!(patches.is_empty());
+        assert_eq!(memory, (0..8).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_revert_stacked() {
+        let mut memory: Vec<u8> = (0..8).collect();
+        // A 0..4, B 1..5 and C 2..6, each on top of the previous ones
+        let mut state = apply_patches(
+            &mut memory,
+            &[(0, [0xaa; 4]), (1, [0xbb; 4]), (2, [0xcc; 4])],
+        );
+        assert_eq!(memory, [0xaa, 0xbb, 0xcc, 0xcc, 0xcc, 0xcc, 6, 7]);
+
+        // B read A's bytes 1..4, C read B's bytes and keeps them
+        let (runs, patches) = state.revert_plan(0);
+        apply(&mut memory, runs);
+        assert_eq!(memory, [0, 0xbb, 0xcc, 0xcc, 0xcc, 0xcc, 6, 7]);
+        assert_eq!(patches[0].original, [1, 2, 3, 4]);
+        assert_eq!(patches[1].original, [0xbb, 0xbb, 0xbb, 5]);
+        state.patches = patches;
+
+        let (runs, patches) = state.revert_plan(1);
+        apply(&mut memory, runs);
+        assert_eq!(memory, [0, 0xbb, 0xbb, 0xbb, 0xbb, 5, 6, 7]);
+        state.patches = patches;
+
+        let (runs, patches) = state.revert_plan(0);
+        apply(&mut memory, runs);
+        assert!(patches.is_empty());
+        assert_eq!(memory, (0..8).collect::<Vec<u8>>());
+    }
+}
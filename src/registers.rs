@@ -0,0 +1,529 @@
+// typed register access sized from the register's real width
+
+use crate::{BNDebuggerEventType, DebugRegister, DebuggerController, DebuggerEvent};
+use binaryninjacore_sys as sys;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fmt;
+use std::sync::Mutex;
+
+/// Size of the core's register buffer (512 bits)
+pub(crate) const MAX_REGISTER_SIZE: usize = 64;
+
+const INVALID_REGISTER: u32 = 0xffff_ffff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterValue {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    U128(u128),
+    V256([u8; 32]),
+    V512([u8; 64]),
+    /// x87 80-bit extended precision, little-endian
+    F80([u8; 10]),
+}
+
+impl RegisterValue {
+    /// Decode little-endian bytes, the length selects the variant
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(match bytes.len() {
+            1 => Self::U8(bytes[0]),
+            2 => Self::U16(u16::from_le_bytes(bytes.try_into().ok()?)),
+            4 => Self::U32(u32::from_le_bytes(bytes.try_into().ok()?)),
+            8 => Self::U64(u64::from_le_bytes(bytes.try_into().ok()?)),
+            10 => Self::F80(bytes.try_into().ok()?),
+            16 => Self::U128(u128::from_le_bytes(bytes.try_into().ok()?)),
+            32 => Self::V256(bytes.try_into().ok()?),
+            64 => Self::V512(bytes.try_into().ok()?),
+            _ => return None,
+        })
+    }
+
+    /// Width in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Self::U8(_) => 1,
+            Self::U16(_) => 2,
+            Self::U32(_) => 4,
+            Self::U64(_) => 8,
+            Self::F80(_) => 10,
+            Self::U128(_) => 16,
+            Self::V256(_) => 32,
+            Self::V512(_) => 64,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::U8(v) => vec![*v],
+            Self::U16(v) => v.to_le_bytes().to_vec(),
+            Self::U32(v) => v.to_le_bytes().to_vec(),
+            Self::U64(v) => v.to_le_bytes().to_vec(),
+            Self::U128(v) => v.to_le_bytes().to_vec(),
+            Self::F80(v) => v.to_vec(),
+            Self::V256(v) => v.to_vec(),
+            Self::V512(v) => v.to_vec(),
+        }
+    }
+
+    /// Value of an integer register up to 64 bits wide
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Self::U8(v) => Some(v as u64),
+            Self::U16(v) => Some(v as u64),
+            Self::U32(v) => Some(v as u64),
+            Self::U64(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_u128(&self) -> Option<u128> {
+        match *self {
+            Self::U128(v) => Some(v),
+            _ => self.as_u64().map(|v| v as u128),
+        }
+    }
+
+    /// Convert an x87 register to the nearest f64
+    pub fn as_f64(&self) -> Option<f64> {
+        let Self::F80(bytes) = self else {
+            return None;
+        };
+        let mantissa = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let sign = if bytes[9] & 0x80 != 0 { -1.0 } else { 1.0 };
+        let exponent = (u16::from_le_bytes([bytes[8], bytes[9]]) & 0x7fff) as i32;
+        let value = match exponent {
+            0 if mantissa == 0 => 0.0,
+            0x7fff if mantissa << 1 == 0 => f64::INFINITY,
+            0x7fff => f64::NAN,
+            // explicit integer bit, denormals use the minimum exponent
+            _ => {
+                let exponent = exponent.max(1) - 16383 - 63;
+                mantissa as f64 * 2f64.powi(exponent)
+            }
+        };
+        Some(sign * value)
+    }
+}
+
+impl fmt::Display for RegisterValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::U128(v) => write!(f, "0x{:032x}", v),
+            Self::F80(_) => write!(f, "{}", self.as_f64().unwrap_or(f64::NAN)),
+            Self::V256(v) => write!(f, "{:02x?}", v),
+            Self::V512(v) => write!(f, "{:02x?}", v),
+            _ => write!(f, "0x{:x}", self.as_u64().unwrap_or_default()),
+        }
+    }
+}
+
+impl From<u8> for RegisterValue {
+    fn from(v: u8) -> Self {
+        Self::U8(v)
+    }
+}
+
+impl From<u16> for RegisterValue {
+    fn from(v: u16) -> Self {
+        Self::U16(v)
+    }
+}
+
+impl From<u32> for RegisterValue {
+    fn from(v: u32) -> Self {
+        Self::U32(v)
+    }
+}
+
+impl From<u64> for RegisterValue {
+    fn from(v: u64) -> Self {
+        Self::U64(v)
+    }
+}
+
+impl From<u128> for RegisterValue {
+    fn from(v: u128) -> Self {
+        Self::U128(v)
+    }
+}
+
+/// Register file captured at one stop
+#[derive(Debug, Clone, Default)]
+pub struct RegisterSnapshot {
+    registers: Vec<DebugRegister>,
+}
+
+impl RegisterSnapshot {
+    pub fn get(&self, name: &str) -> Option<&DebugRegister> {
+        self.registers.iter().find(|r| r.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &DebugRegister> {
+        self.registers.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.registers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.registers.is_empty()
+    }
+
+    /// Registers whose value differs between two snapshots, in `cur` order.
+    ///
+    /// Registers present in only one snapshot are reported with the missing side as None.
+    pub fn diff(prev: &RegisterSnapshot, cur: &RegisterSnapshot) -> Vec<RegisterChange> {
+        let mut changes: Vec<RegisterChange> = cur
+            .iter()
+            .filter_map(|reg| {
+                let old = prev.get(&reg.name).map(|r| r.value.clone());
+                (old.as_deref() != Some(&reg.value[..])).then(|| RegisterChange {
+                    name: reg.name.clone(),
+                    old,
+                    new: Some(reg.value.clone()),
+                })
+            })
+            .collect();
+        changes.extend(
+            prev.iter()
+                .filter(|reg| cur.get(&reg.name).is_none())
+                .map(|reg| RegisterChange {
+                    name: reg.name.clone(),
+                    old: Some(reg.value.clone()),
+                    new: None,
+                }),
+        );
+        changes
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub name: String,
+    pub old: Option<Vec<u8>>,
+    pub new: Option<Vec<u8>>,
+}
+
+impl RegisterChange {
+    pub fn old_value(&self) -> Option<RegisterValue> {
+        RegisterValue::from_bytes(self.old.as_deref()?)
+    }
+
+    pub fn new_value(&self) -> Option<RegisterValue> {
+        RegisterValue::from_bytes(self.new.as_deref()?)
+    }
+}
+
+impl fmt::Display for RegisterChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |bytes: &Option<Vec<u8>>| match bytes {
+            None => "-".to_owned(),
+            Some(b) => RegisterValue::from_bytes(b)
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| format!("{:02x?}", b)),
+        };
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.name,
+            show(&self.old),
+            show(&self.new)
+        )
+    }
+}
+
+#[derive(Default)]
+struct WidthTable {
+    /// Architecture the widths belong to
+    arch: usize,
+    /// The adapter's register list was merged in
+    from_adapter: bool,
+    /// None for names neither the adapter nor the architecture know
+    widths: HashMap<String, Option<usize>>,
+}
+
+/// Register widths by name, learned once per target architecture so typed
+/// accesses do not fetch the whole register file each time
+#[derive(Default)]
+pub(crate) struct RegisterWidths {
+    table: Mutex<WidthTable>,
+}
+
+/// Whether `value` fits in a register of `width` bytes
+fn fits_width(value: u64, width: usize) -> bool {
+    width >= 8 || value >> (width * 8) == 0
+}
+
+impl DebuggerController {
+    /// Architecture of the debugged target, null while not connected
+    pub(crate) fn arch(&self) -> Option<*mut sys::BNArchitecture> {
+        let arch = unsafe { crate::ffi::BNDebuggerGetRemoteArchitecture(self.handle()) };
+        if arch.is_null() {
+            None
+        } else {
+            Some(arch as *mut sys::BNArchitecture)
+        }
+    }
+
+    /// Width of a register in bytes.
+    ///
+    /// Uses the adapter's register list, falling back to the architecture for
+    /// sub-registers the adapter does not report (e.g. `eax`). Widths are
+    /// remembered until the target architecture changes.
+    pub fn register_width(&self, name: &str) -> Option<usize> {
+        let arch = self.arch()?;
+        let load_adapter = {
+            let mut table = self.inner.register_widths.table.lock().unwrap();
+            if table.arch != arch as usize {
+                *table = WidthTable {
+                    arch: arch as usize,
+                    ..Default::default()
+                };
+            }
+            if let Some(&width) = table.widths.get(name) {
+                return width;
+            }
+            !table.from_adapter
+        };
+
+        let adapter = if load_adapter {
+            self.registers()
+        } else {
+            Vec::new()
+        };
+        let mut table = self.inner.register_widths.table.lock().unwrap();
+        if !adapter.is_empty() {
+            for reg in &adapter {
+                table.widths.insert(reg.name.clone(), Some(reg.size()));
+            }
+            table.from_adapter = true;
+            if let Some(&width) = table.widths.get(name) {
+                return width;
+            }
+        }
+        let width = Self::arch_register_width(arch, name);
+        // an empty list means the adapter was not ready, try again next time
+        if table.from_adapter {
+            table.widths.insert(name.to_owned(), width);
+        }
+        width
+    }
+
+    fn arch_register_width(arch: *mut sys::BNArchitecture, name: &str) -> Option<usize> {
+        let name_cstr = CString::new(name).ok()?;
+        unsafe {
+            let reg = sys::BNGetArchitectureRegisterByName(arch, name_cstr.as_ptr());
+            if reg == INVALID_REGISTER {
+                return None;
+            }
+            let info = sys::BNGetArchitectureRegisterInfo(arch, reg);
+            (info.size > 0 && info.size <= MAX_REGISTER_SIZE).then_some(info.size)
+        }
+    }
+
+    /// Read a register, None if it is unknown or has an unsupported width
+    pub fn register(&self, name: &str) -> Option<RegisterValue> {
+        let width = self.register_width(name)?;
+        let bytes = self.get_register_value(name);
+        RegisterValue::from_bytes(bytes.get(..width)?)
+    }
+
+    /// Write a register, the value must have exactly the register's width
+    pub fn set_register(&self, name: &str, value: RegisterValue) -> bool {
+        let Some(width) = self.register_width(name) else {
+            log::warn!("unknown register {}", name);
+            return false;
+        };
+        if value.size() != width {
+            log::warn!(
+                "{} is {} bytes wide, value is {} bytes",
+                name,
+                width,
+                value.size()
+            );
+            return false;
+        }
+        self.set_register_value(name, &value.to_bytes())
+    }
+
+    /// Read an integer register up to 64 bits wide
+    pub fn reg_u64(&self, name: &str) -> Option<u64> {
+        self.register(name)?.as_u64()
+    }
+
+    /// Write an integer register up to 64 bits wide.
+    ///
+    /// Fails if the register is wider than 64 bits or the value does not fit in it.
+    pub fn set_reg_u64(&self, name: &str, value: u64) -> bool {
+        let Some(width) = self.register_width(name) else {
+            log::warn!("unknown register {}", name);
+            return false;
+        };
+        if width > 8 || !fits_width(value, width) {
+            log::warn!(
+                "0x{:x} does not fit in {} ({} bytes wide)",
+                value,
+                name,
+                width
+            );
+            return false;
+        }
+        self.set_register_value(name, &value.to_le_bytes()[..width])
+    }
+
+    pub fn registers_snapshot(&self) -> RegisterSnapshot {
+        RegisterSnapshot {
+            registers: self.registers(),
+        }
+    }
+
+    /// Call `callback` with the registers that changed since the previous stop.
+    ///
+    /// Fires after every stop (possibly with no changes) and whenever a register
+    /// is written while stopped. Returns the event callback index for
+    /// `remove_event_callback`.
+    pub fn on_registers_changed<F>(&self, callback: F) -> usize
+    where
+        F: Fn(&RegisterSnapshot, &[RegisterChange]) + Send + Sync + 'static,
+    {
+        let controller = self.downgrade();
+        let last = Mutex::new(self.is_connected().then(|| self.registers_snapshot()));
+        self.register_event_callback("rust-register-changes", move |event: &DebuggerEvent| {
+            use BNDebuggerEventType::*;
+            let stopped = match event.event_type {
+                TargetStoppedEventType => true,
+                RegisterChangedEvent => false,
+                TargetExitedEventType | DetachedEventType => {
+                    *last.lock().unwrap() = None;
+                    return;
+                }
+                _ => return,
+            };
+            let Some(controller) = controller.upgrade() else {
+                return;
+            };
+
+            // bypass the stop cache, its own callback may not have run yet
+            let cur = RegisterSnapshot {
+                registers: controller.registers_uncached(),
+            };
+            let mut last = last.lock().unwrap();
+            let changes = match last.as_ref() {
+                Some(prev) => RegisterSnapshot::diff(prev, &cur),
+                // first stop, everything is new
+                None => RegisterSnapshot::diff(&RegisterSnapshot::default(), &cur),
+            };
+            if stopped || !changes.is_empty() {
+                callback(&cur, &changes);
+            }
+            *last = Some(cur);
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_value_from_bytes() {
+        assert_eq!(
+            RegisterValue::from_bytes(&[0x12]),
+            Some(RegisterValue::U8(0x12))
+        );
+        assert_eq!(
+            RegisterValue::from_bytes(&[0x34, 0x12]),
+            Some(RegisterValue::U16(0x1234))
+        );
+        assert_eq!(
+            RegisterValue::from_bytes(&0xdead_beefu32.to_le_bytes()),
+            Some(RegisterValue::U32(0xdead_beef))
+        );
+        let value = RegisterValue::from_bytes(&0x1122_3344_5566_7788u64.to_le_bytes()).unwrap();
+        assert_eq!(value.as_u64(), Some(0x1122_3344_5566_7788));
+        assert_eq!(value.to_bytes(), 0x1122_3344_5566_7788u64.to_le_bytes());
+        let wide = RegisterValue::from_bytes(&u128::MAX.to_le_bytes()).unwrap();
+        assert_eq!(wide.as_u64(), None);
+        assert_eq!(wide.as_u128(), Some(u128::MAX));
+        assert_eq!(RegisterValue::from_bytes(&[0; 32]).unwrap().size(), 32);
+        assert_eq!(RegisterValue::from_bytes(&[0; 64]).unwrap().size(), 64);
+        assert_eq!(RegisterValue::from_bytes(&[0; 10]).unwrap().size(), 10);
+        for len in [0, 3, 5, 12, 48] {
+            assert_eq!(RegisterValue::from_bytes(&vec![0; len]), None);
+        }
+    }
+
+    fn f80(sign: bool, exponent: u16, mantissa: u64) -> RegisterValue {
+        let mut bytes = [0u8; 10];
+        bytes[..8].copy_from_slice(&mantissa.to_le_bytes());
+        let top = exponent | if sign { 0x8000 } else { 0 };
+        bytes[8..].copy_from_slice(&top.to_le_bytes());
+        RegisterValue::F80(bytes)
+    }
+
+    #[test]
+    fn test_f80_as_f64() {
+        let one = 1u64 << 63;
+        assert_eq!(f80(false, 16383, one).as_f64(), Some(1.0));
+        assert_eq!(f80(true, 16384, one | one >> 1).as_f64(), Some(-3.0));
+        assert_eq!(f80(false, 16382, one).as_f64(), Some(0.5));
+        assert_eq!(f80(false, 0, 0).as_f64(), Some(0.0));
+        assert_eq!(f80(false, 0x7fff, one).as_f64(), Some(f64::INFINITY));
+        assert_eq!(f80(true, 0x7fff, one).as_f64(), Some(f64::NEG_INFINITY));
+        assert!(f80(false, 0x7fff, one | 1).as_f64().unwrap().is_nan());
+        assert_eq!(RegisterValue::U64(1).as_f64(), None);
+        assert_eq!(f80(false, 16383, one).to_string(), "1");
+    }
+
+    #[test]
+    fn test_fits_width() {
+        assert!(fits_width(0xff, 1));
+        assert!(!fits_width(0x100, 1));
+        assert!(fits_width(0xffff, 2));
+        assert!(!fits_width(0x1_0000, 2));
+        assert!(fits_width(0xffff_ffff, 4));
+        assert!(!fits_width(0x1_0000_0000, 4));
+        assert!(fits_width(u64::MAX, 8));
+    }
+
+    fn snapshot(registers: &[(&str, u64)]) -> RegisterSnapshot {
+        RegisterSnapshot {
+            registers: registers
+                .iter()
+                .enumerate()
+                .map(|(i, &(name, value))| DebugRegister {
+                    name: name.to_owned(),
+                    value: value.to_le_bytes().to_vec(),
+                    width: 64,
+                    register_index: i,
+                    hint: String::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let prev = snapshot(&[("rax", 1), ("rbx", 2), ("rcx", 3), ("fs", 9)]);
+        let cur = snapshot(&[("rip", 0x1000), ("rcx", 4), ("rbx", 2), ("rax", 5)]);
+        let changes = RegisterSnapshot::diff(&prev, &cur);
+        let bytes = |value: u64| Some(value.to_le_bytes().to_vec());
+
+        // changed and new registers in `cur` order, then the ones gone from `cur`
+        let names: Vec<&str> = changes.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["rip", "rcx", "rax", "fs"]);
+        assert_eq!(changes[0].old, None);
+        assert_eq!(changes[0].new, bytes(0x1000));
+        assert_eq!(changes[1].old, bytes(3));
+        assert_eq!(changes[1].new_value(), Some(RegisterValue::U64(4)));
+        assert_eq!(changes[2].old_value(), Some(RegisterValue::U64(1)));
+        assert_eq!(changes[3].old, bytes(9));
+        assert_eq!(changes[3].new, None);
+
+        assert!(RegisterSnapshot::diff(&cur, &cur).is_empty());
+    }
+}
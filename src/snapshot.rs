@@ -0,0 +1,285 @@
+// memory snapshots taken at a stop and diffs between them
+
+use crate::memory::{MemoryRegion, MemorySpan, PARALLEL_CHUNK_SIZE};
+use crate::types::{core_string, CoreType, CoreView};
+use crate::DebuggerController;
+use binaryninjacore_sys as sys;
+use std::fmt;
+
+/// Memory contents captured at a stop, chunks are sorted and non overlapping
+#[derive(Debug, Clone, Default)]
+pub struct MemorySnapshot {
+    pub chunks: Vec<MemorySpan>,
+}
+
+impl MemorySnapshot {
+    fn push(&mut self, address: u64, data: Vec<u8>) {
+        if data.is_empty() {
+            return;
+        }
+        // merge with the previous chunk when contiguous
+        if let Some(last) = self.chunks.last_mut() {
+            if last.end() == address {
+                last.data.extend_from_slice(&data);
+                return;
+            }
+        }
+        self.chunks.push(MemorySpan { address, data });
+    }
+
+    fn sort(&mut self) {
+        let mut chunks = std::mem::take(&mut self.chunks);
+        chunks.sort_by_key(|c| c.address);
+        for c in chunks {
+            self.push(c.address, c.data);
+        }
+    }
+
+    /// Total number of captured bytes
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(|c| c.data.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Read captured bytes, None if any part of the range was not captured
+    pub fn read(&self, address: u64, size: usize) -> Option<&[u8]> {
+        let chunk = self
+            .chunks
+            .iter()
+            .find(|c| address >= c.address && address < c.end())?;
+        let start = (address - chunk.address) as usize;
+        chunk.data.get(start..start + size)
+    }
+
+    /// Compare two snapshots, returning the changed ranges.
+    ///
+    /// Only memory captured by both snapshots is compared.
+    pub fn diff(a: &MemorySnapshot, b: &MemorySnapshot) -> Vec<MemoryChange> {
+        let mut changes = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < a.chunks.len() && j < b.chunks.len() {
+            let (ca, cb) = (&a.chunks[i], &b.chunks[j]);
+            let start = ca.address.max(cb.address);
+            let end = ca.end().min(cb.end());
+
+            if start < end {
+                let old = &ca.data[(start - ca.address) as usize..(end - ca.address) as usize];
+                let new = &cb.data[(start - cb.address) as usize..(end - cb.address) as usize];
+
+                let mut k = 0;
+                while k < old.len() {
+                    if old[k] == new[k] {
+                        k += 1;
+                        continue;
+                    }
+                    let run = k;
+                    while k < old.len() && old[k] != new[k] {
+                        k += 1;
+                    }
+                    changes.push(MemoryChange {
+                        address: start + run as u64,
+                        old: old[run..k].to_vec(),
+                        new: new[run..k].to_vec(),
+                        annotation: None,
+                    });
+                }
+            }
+
+            if ca.end() <= cb.end() {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        changes
+    }
+}
+
+/// Where a changed address lives according to the analysis
+#[derive(Debug, Clone)]
+pub struct TypeAnnotation {
+    /// start of the data variable containing the change
+    pub variable: u64,
+    pub variable_name: Option<String>,
+    pub type_name: String,
+    /// field path inside the variable, e.g. `entries[2].len`
+    pub field: Option<String>,
+}
+
+impl fmt::Display for TypeAnnotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.variable_name {
+            Some(name) => write!(f, "{} {}", self.type_name, name)?,
+            None => write!(f, "{} @ 0x{:x}", self.type_name, self.variable)?,
+        }
+        if let Some(field) = &self.field {
+            if field.starts_with('[') {
+                write!(f, "{}", field)?;
+            } else {
+                write!(f, ".{}", field)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryChange {
+    pub address: u64,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+    pub annotation: Option<TypeAnnotation>,
+}
+
+impl fmt::Display for MemoryChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "0x{:x} [{}]: {:02x?} -> {:02x?}",
+            self.address,
+            self.old.len(),
+            self.old,
+            self.new
+        )?;
+        if let Some(annotation) = &self.annotation {
+            write!(f, " ({})", annotation)?;
+        }
+        Ok(())
+    }
+}
+
+/// Data variables of a view sorted by address, used to annotate changes
+struct DataVariables {
+    view: CoreView,
+    vars: Vec<(u64, u64, CoreType)>,
+}
+
+impl DataVariables {
+    fn new(view: CoreView) -> Self {
+        let mut vars = Vec::new();
+        unsafe {
+            let mut count = 0usize;
+            let ptr = sys::BNGetDataVariables(view.handle(), &mut count);
+            if !ptr.is_null() {
+                for var in std::slice::from_raw_parts(ptr, count) {
+                    if let Some(ty) = CoreType::from_borrowed(var.type_) {
+                        vars.push((var.address, ty.width().max(1), ty));
+                    }
+                }
+                sys::BNFreeDataVariables(ptr, count);
+            }
+        }
+        vars.sort_by_key(|v| v.0);
+        Self { view, vars }
+    }
+
+    fn annotate(&self, address: u64) -> Option<TypeAnnotation> {
+        let idx = self
+            .vars
+            .partition_point(|v| v.0 <= address)
+            .checked_sub(1)?;
+        let (start, width, ty) = &self.vars[idx];
+        if address >= start + width {
+            return None;
+        }
+
+        let variable_name = unsafe {
+            let sym = sys::BNGetSymbolByAddress(self.view.handle(), *start, std::ptr::null());
+            if sym.is_null() {
+                None
+            } else {
+                let name = core_string(sys::BNGetSymbolFullName(sym));
+                sys::BNFreeSymbol(sym);
+                name
+            }
+        };
+        let field = unsafe { CoreType::from_borrowed(ty.handle()) }
+            .and_then(|t| t.field_path(self.view.handle(), address - start));
+
+        Some(TypeAnnotation {
+            variable: *start,
+            variable_name,
+            type_name: ty.name(),
+            field,
+        })
+    }
+}
+
+impl DebuggerController {
+    /// Capture the contents of the given regions at the current stop.
+    ///
+    /// Pages that cannot be read are left out of the snapshot.
+    pub fn snapshot(&self, regions: &[MemoryRegion]) -> MemorySnapshot {
+        let mut snapshot = MemorySnapshot::default();
+        for region in regions {
+            let read = if region.size() > PARALLEL_CHUNK_SIZE {
+                self.read_memory_parallel(region.start, region.size() as usize)
+            } else {
+                self.read_memory_partial(region.start, region.size() as usize)
+            };
+            snapshot.chunks.extend(read.spans);
+        }
+        snapshot.sort();
+        snapshot
+    }
+
+    /// Compare two snapshots, annotating each change with the data variable
+    /// (and field) it falls into in the live view, when one is defined.
+    pub fn diff(&self, a: &MemorySnapshot, b: &MemorySnapshot) -> Vec<MemoryChange> {
+        let mut changes = MemorySnapshot::diff(a, b);
+        if changes.is_empty() {
+            return changes;
+        }
+
+        let Some(view) = self.live_view() else {
+            return changes;
+        };
+        let vars = DataVariables::new(view);
+        for change in &mut changes {
+            change.annotation = vars.annotate(change.address);
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(chunks: &[(u64, &[u8])]) -> MemorySnapshot {
+        let mut snapshot = MemorySnapshot::default();
+        for &(address, data) in chunks {
+            snapshot.chunks.push(MemorySpan {
+                address,
+                data: data.to_vec(),
+            });
+        }
+        snapshot.sort();
+        snapshot
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let a = snapshot(&[(0x1000, &[1, 2, 3, 4, 5, 6]), (0x2000, &[0; 4])]);
+        // contiguous chunks are merged, 0x1006.. was not captured by `a`
+        let b = snapshot(&[(0x1004, &[9, 6, 7, 8]), (0x1000, &[1, 0, 0, 4])]);
+        assert_eq!(b.chunks.len(), 1);
+        assert_eq!(b.read(0x1002, 4), Some(&[0u8, 4, 9, 6][..]));
+        assert_eq!(b.read(0x1006, 4), None);
+
+        let changes = MemorySnapshot::diff(&a, &b);
+        let changes: Vec<(u64, Vec<u8>, Vec<u8>)> = changes
+            .into_iter()
+            .map(|c| (c.address, c.old, c.new))
+            .collect();
+        assert_eq!(
+            changes,
+            [(0x1001, vec![2, 3], vec![0, 0]), (0x1004, vec![5], vec![9]),]
+        );
+        assert!(MemorySnapshot::diff(&a, &a).is_empty());
+        assert!(MemorySnapshot::diff(&a, &MemorySnapshot::default()).is_empty());
+    }
+}
@@ -0,0 +1,412 @@
+// address <-> symbol resolution across the main view and every loaded module
+
+use crate::elf::{link_base, ElfHeader};
+use crate::types::{core_string, CoreView};
+use crate::{DebugModule, DebuggerController};
+use binaryninjacore_sys as sys;
+use binaryninjacore_sys::BNSymbolType;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// An address as a symbol of a module and the offset into it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolLocation {
+    /// Short name of the containing module
+    pub module: String,
+    /// Closest symbol at or below the address, None if the module has none
+    pub symbol: Option<String>,
+    /// Offset from the symbol, or from the module base without one
+    pub offset: u64,
+}
+
+impl fmt::Display for SymbolLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.symbol {
+            Some(symbol) => write!(f, "{}!{}", self.module, symbol)?,
+            None => write!(f, "{}", self.module)?,
+        }
+        if self.offset != 0 {
+            write!(f, "+0x{:x}", self.offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// Symbols of one module at runtime addresses
+struct ModuleSymbols {
+    module: DebugModule,
+    /// Built with the analysis of a view, not only the file's symbol tables
+    from_view: bool,
+    by_address: BTreeMap<u64, String>,
+    by_name: HashMap<String, u64>,
+}
+
+impl ModuleSymbols {
+    fn contains(&self, address: u64) -> bool {
+        address >= self.module.address && address - self.module.address < self.module.size as u64
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.module.short_name == name
+            || self.module.name == name
+            || Path::new(&self.module.name)
+                .file_name()
+                .is_some_and(|file| file == name)
+    }
+
+    fn add(&mut self, address: u64, name: String) {
+        if !self.contains(address) {
+            return;
+        }
+        self.by_name.entry(name.clone()).or_insert(address);
+        self.by_address.entry(address).or_insert(name);
+    }
+}
+
+/// (module path, base, analysis view opened) of every module the index was built from
+type IndexKey = Vec<(String, u64, bool)>;
+
+#[derive(Default)]
+struct SymbolIndex {
+    key: IndexKey,
+    /// Stop cache and event generations the key was last checked at
+    checked: Option<(u64, u64)>,
+    /// Base of the main executable
+    main: Option<u64>,
+    /// By module base, the module containing an address is the last one at or below it
+    modules: BTreeMap<u64, Arc<ModuleSymbols>>,
+}
+
+impl SymbolIndex {
+    fn module_at(&self, address: u64) -> Option<&ModuleSymbols> {
+        let (_, module) = self.modules.range(..=address).next_back()?;
+        module.contains(address).then_some(module.as_ref())
+    }
+
+    /// Address of `module!symbol` or `symbol`, the latter looked up in the
+    /// main executable first. Names that merely contain a `!`, e.g.
+    /// `operator!=`, are also tried as a whole.
+    fn lookup(&self, name: &str) -> Option<u64> {
+        let qualified = name.split_once('!').and_then(|(module, symbol)| {
+            self.modules
+                .values()
+                .filter(|m| m.matches(module))
+                .find_map(|m| m.by_name.get(symbol).copied())
+        });
+        qualified.or_else(|| {
+            let main = self.main.and_then(|base| self.modules.get(&base));
+            main.into_iter()
+                .chain(self.modules.values())
+                .find_map(|m| m.by_name.get(name).copied())
+        })
+    }
+}
+
+/// Symbol index of the loaded modules, rebuilt when the module list changes
+/// or a `module_view` is opened. Symbols of unchanged modules are kept.
+#[derive(Default)]
+pub(crate) struct SymbolCache {
+    index: Mutex<SymbolIndex>,
+    /// Bumped by every debugger event, the module list only changes with one
+    events: Arc<AtomicU64>,
+    callback: Mutex<Option<usize>>,
+}
+
+impl SymbolCache {
+    /// Check the module list again on the next lookup
+    pub(crate) fn invalidate(&self) {
+        self.events.fetch_add(1, Ordering::AcqRel);
+    }
+
+    pub(crate) fn take_callback(&self) -> Option<usize> {
+        self.callback.lock().unwrap().take()
+    }
+}
+
+impl DebuggerController {
+    /// Module, closest preceding symbol and offset of an address.
+    ///
+    /// Symbols come from the analysis of the live view for the main
+    /// executable, of `module_view` views for modules that have one, and from
+    /// the ELF symbol tables of the files on disk otherwise.
+    pub fn symbolize(&self, address: u64) -> Option<SymbolLocation> {
+        let index = self.symbol_index();
+        let module = index.module_at(address)?;
+        let location = match module.by_address.range(..=address).next_back() {
+            Some((start, name)) => SymbolLocation {
+                module: module.module.short_name.clone(),
+                symbol: Some(name.clone()),
+                offset: address - start,
+            },
+            None => SymbolLocation {
+                module: module.module.short_name.clone(),
+                symbol: None,
+                offset: address - module.module.address,
+            },
+        };
+        Some(location)
+    }
+
+    /// Address of a symbol given as `module!symbol` or `symbol`, optionally
+    /// followed by `+offset`.
+    ///
+    /// The module is matched by short name, file name or path. A bare symbol
+    /// is looked up in the main executable first, then in every other module.
+    pub fn resolve(&self, name: &str) -> Option<u64> {
+        let (name, offset) = split_offset(name);
+        let address = self.symbol_index().lookup(name)?;
+        Some(address.wrapping_add(offset))
+    }
+
+    /// `module!symbol+0x..` for addresses inside a module, else plain hex
+    pub fn format_address(&self, address: u64) -> String {
+        match self.symbolize(address) {
+            Some(location) => location.to_string(),
+            None => format!("0x{:x}", address),
+        }
+    }
+
+    /// The current index, rebuilt first if it is stale.
+    ///
+    /// The module list is only read again after a resume or a debugger event.
+    fn symbol_index(&self) -> std::sync::MutexGuard<'_, SymbolIndex> {
+        self.watch_symbol_events();
+        let stamp = (
+            self.inner.cache.generation(),
+            self.inner.symbols.events.load(Ordering::Acquire),
+        );
+        {
+            let index = self.inner.symbols.index.lock().unwrap();
+            if index.checked == Some(stamp) {
+                return index;
+            }
+        }
+
+        let modules = self.modules();
+        let main = self.main_module_of(&modules).map(|m| m.address);
+        self.prune_module_views(&modules);
+        let views: Vec<Option<CoreView>> = modules
+            .iter()
+            .map(|m| {
+                if Some(m.address) == main {
+                    self.live_view()
+                } else {
+                    self.opened_module_view(m)
+                }
+            })
+            .collect();
+        let key: IndexKey = modules
+            .iter()
+            .zip(&views)
+            .map(|(m, view)| (m.name.clone(), m.address, view.is_some()))
+            .collect();
+
+        let mut index = self.inner.symbols.index.lock().unwrap();
+        if index.key != key {
+            let previous = std::mem::take(&mut index.modules);
+            for (module, view) in modules.into_iter().zip(views) {
+                let symbols = match previous.get(&module.address) {
+                    Some(old)
+                        if old.module.name == module.name && old.from_view == view.is_some() =>
+                    {
+                        Arc::clone(old)
+                    }
+                    _ => Arc::new(module_symbols(module, view.as_ref())),
+                };
+                index.modules.insert(symbols.module.address, symbols);
+            }
+            index.key = key;
+            index.main = main;
+        }
+        index.checked = Some(stamp);
+        index
+    }
+
+    fn watch_symbol_events(&self) {
+        let symbols = &self.inner.symbols;
+        let mut callback = symbols.callback.lock().unwrap();
+        if callback.is_none() {
+            let events = Arc::clone(&symbols.events);
+            *callback = Some(self.register_event_callback("rust-symbol-cache", move |_| {
+                events.fetch_add(1, Ordering::AcqRel);
+            }));
+        }
+    }
+}
+
+/// Split a `+offset` suffix off a location, only if it parses as an offset,
+/// so names like `operator+=` stay whole
+fn split_offset(name: &str) -> (&str, u64) {
+    name.rsplit_once('+')
+        .and_then(|(name, offset)| Some((name, parse_offset(offset)?)))
+        .unwrap_or((name, 0))
+}
+
+fn parse_offset(offset: &str) -> Option<u64> {
+    match offset.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => offset.parse().ok(),
+    }
+}
+
+fn module_symbols(module: DebugModule, view: Option<&CoreView>) -> ModuleSymbols {
+    let mut symbols = ModuleSymbols {
+        module,
+        from_view: view.is_some(),
+        by_address: BTreeMap::new(),
+        by_name: HashMap::new(),
+    };
+    // analysis names take precedence over the symbol tables
+    if let Some(view) = view {
+        for (address, name) in view_symbols(view) {
+            symbols.add(address, name);
+        }
+    }
+    for (address, name) in file_symbols(&symbols.module) {
+        symbols.add(address, name);
+    }
+    symbols
+}
+
+/// Function and data symbols of a view, functions first
+fn view_symbols(view: &CoreView) -> Vec<(u64, String)> {
+    use BNSymbolType::*;
+    let mut result = Vec::new();
+    unsafe {
+        let mut count = 0usize;
+        let list = sys::BNGetSymbols(view.handle(), &mut count, std::ptr::null());
+        if list.is_null() {
+            return Vec::new();
+        }
+        for &symbol in std::slice::from_raw_parts(list, count) {
+            let rank = match sys::BNGetSymbolType(symbol) {
+                FunctionSymbol | LibraryFunctionSymbol => 0,
+                DataSymbol => 1,
+                ImportedFunctionSymbol => 2,
+                _ => continue,
+            };
+            if let Some(name) = core_string(sys::BNGetSymbolShortName(symbol)) {
+                result.push((rank, sys::BNGetSymbolAddress(symbol), name));
+            }
+        }
+        sys::BNFreeSymbolList(list, count);
+    }
+    result.sort_by_key(|&(rank, address, _)| (rank, address));
+    result
+        .into_iter()
+        .map(|(_, address, name)| (address, name))
+        .collect()
+}
+
+/// `.symtab` and `.dynsym` symbols of the module's file, rebased to its load address
+fn file_symbols(module: &DebugModule) -> Vec<(u64, String)> {
+    let Ok(file) = std::fs::read(&module.name) else {
+        return Vec::new();
+    };
+    let Some(header) = ElfHeader::parse(&file) else {
+        return Vec::new();
+    };
+    let headers = header.program_headers(file.get(header.phoff as usize..).unwrap_or_default());
+    let Some(base) = link_base(&headers) else {
+        return Vec::new();
+    };
+    let bias = module.address.wrapping_sub(base);
+    let mut symbols = header.symbols(&file);
+    // functions first so they name addresses shared with data symbols
+    symbols.sort_by_key(|s| (!s.is_function, s.address));
+    symbols
+        .into_iter()
+        .map(|s| (s.address.wrapping_add(bias), s.name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(name: &str, address: u64, symbols: &[(&str, u64)]) -> Arc<ModuleSymbols> {
+        let mut module = ModuleSymbols {
+            module: DebugModule {
+                name: name.to_owned(),
+                short_name: Path::new(name)
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned(),
+                address,
+                size: 0x10000,
+                loaded: true,
+            },
+            from_view: false,
+            by_address: BTreeMap::new(),
+            by_name: HashMap::new(),
+        };
+        for &(symbol, offset) in symbols {
+            module.add(address + offset, symbol.to_owned());
+        }
+        Arc::new(module)
+    }
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(parse_offset("0x10"), Some(0x10));
+        assert_eq!(parse_offset("16"), Some(16));
+        assert_eq!(parse_offset("0xzz"), None);
+        assert_eq!(parse_offset(""), None);
+        assert_eq!(parse_offset("="), None);
+
+        assert_eq!(split_offset("main+0x10"), ("main", 0x10));
+        assert_eq!(split_offset("libc!malloc+8"), ("libc!malloc", 8));
+        assert_eq!(split_offset("main"), ("main", 0));
+        assert_eq!(split_offset("operator+"), ("operator+", 0));
+        assert_eq!(split_offset("operator+="), ("operator+=", 0));
+        assert_eq!(split_offset("operator++0x4"), ("operator+", 4));
+    }
+
+    #[test]
+    fn test_resolve_lookup() {
+        let mut index = SymbolIndex {
+            main: Some(0x40_0000),
+            ..Default::default()
+        };
+        let libc = module(
+            "/usr/lib/libc.so.6",
+            0x7f00_0000,
+            &[("malloc", 0x100), ("main", 0x200)],
+        );
+        let main = module(
+            "/tmp/prog",
+            0x40_0000,
+            &[("main", 0x10), ("operator!=", 0x20)],
+        );
+        index.modules.insert(0x7f00_0000, libc);
+        index.modules.insert(0x40_0000, main);
+
+        // bare names prefer the main executable
+        assert_eq!(index.lookup("main"), Some(0x40_0010));
+        assert_eq!(index.lookup("malloc"), Some(0x7f00_0100));
+        assert_eq!(index.lookup("libc.so.6!main"), Some(0x7f00_0200));
+        assert_eq!(index.lookup("/usr/lib/libc.so.6!malloc"), Some(0x7f00_0100));
+        assert_eq!(index.lookup("prog!malloc"), None);
+        assert_eq!(index.lookup("operator!="), Some(0x40_0020));
+        assert_eq!(index.lookup("free"), None);
+
+        let location = |address| {
+            let module = index.module_at(address)?;
+            let (start, name) = module.by_address.range(..=address).next_back()?;
+            Some((
+                module.module.short_name.clone(),
+                name.clone(),
+                address - start,
+            ))
+        };
+        assert_eq!(
+            location(0x7f00_0108),
+            Some(("libc.so.6".to_owned(), "malloc".to_owned(), 8))
+        );
+        assert_eq!(location(0x50_0000), None);
+    }
+}
@@ -0,0 +1,246 @@
+// execution traces in the text format of the Tenet trace explorer
+
+use crate::registers::RegisterSnapshot;
+use crate::trace::{AccessKind, TraceEntry, TraceSink};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+/// Registers Tenet knows for x86-64, the instruction pointer last
+const AMD64_REGISTERS: [&str; 17] = [
+    "rax", "rbx", "rcx", "rdx", "rbp", "rsp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip",
+];
+
+/// Registers Tenet knows for x86, the instruction pointer last
+const X86_REGISTERS: [&str; 9] = [
+    "eax", "ebx", "ecx", "edx", "ebp", "esp", "esi", "edi", "eip",
+];
+
+/// Writes a recording as a Tenet trace, one line per instruction.
+///
+/// A line holds the registers changed since the previous line, the
+/// instruction pointer, and the `mr=`/`mw=` memory accesses of the
+/// instruction, the first line every register. Record with
+/// `TraceMode::Instruction` for memory accesses.
+///
+/// With ranges set only instructions inside them are written. Registers
+/// changed by the code in between show up on the next written line.
+pub struct TenetWriter<W: Write> {
+    out: W,
+    ranges: Vec<Range<u64>>,
+    /// Names written, from the architecture of the first snapshot or entry
+    registers: Vec<String>,
+    /// Register values changed since the last line
+    pending: HashMap<String, Vec<u8>>,
+    lines: u64,
+}
+
+impl TenetWriter<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> TenetWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            ranges: Vec::new(),
+            registers: Vec::new(),
+            pending: HashMap::new(),
+            lines: 0,
+        }
+    }
+
+    /// Only write instructions in `start..end`, can be given several times
+    pub fn range(mut self, start: u64, end: u64) -> Self {
+        self.ranges.push(start..end);
+        self
+    }
+
+    /// Number of lines written
+    pub fn len(&self) -> u64 {
+        self.lines
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines == 0
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn in_range(&self, pc: u64) -> bool {
+        self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&pc))
+    }
+
+    /// Pick the register set from the names of the first registers seen
+    fn select_registers(&mut self, names: &[&str]) {
+        if !self.registers.is_empty() {
+            return;
+        }
+        let known: &[&str] = if names.contains(&"rip") {
+            &AMD64_REGISTERS
+        } else if names.contains(&"eip") {
+            &X86_REGISTERS
+        } else {
+            &[]
+        };
+        if known.is_empty() {
+            log::warn!("no Tenet register set for this architecture, writing every register");
+            self.registers = names.iter().map(|&name| name.to_owned()).collect();
+        } else {
+            self.registers = known.iter().map(|&name| name.to_owned()).collect();
+        }
+    }
+
+    fn line(&mut self, entry: &TraceEntry) -> String {
+        let mut line = String::new();
+        let pc_name = if self.registers.iter().any(|name| name == "rip") {
+            "rip"
+        } else {
+            "eip"
+        };
+        for name in &self.registers {
+            let value = if name == pc_name {
+                entry.pc
+            } else {
+                match self.pending.get(name) {
+                    Some(value) => le_value(value),
+                    None => continue,
+                }
+            };
+            let _ = write!(line, "{}=0x{:x},", name, value);
+        }
+        for access in &entry.memory {
+            let kind = match access.kind {
+                AccessKind::Read => "mr",
+                AccessKind::Write => "mw",
+            };
+            let _ = write!(line, "{}=0x{:x}:", kind, access.address);
+            for byte in &access.data {
+                let _ = write!(line, "{:02x}", byte);
+            }
+            line.push(',');
+        }
+        line.pop();
+        line
+    }
+}
+
+impl<W: Write> TraceSink for TenetWriter<W> {
+    fn begin(&mut self, registers: &RegisterSnapshot) -> io::Result<()> {
+        let names: Vec<&str> = registers.iter().map(|r| r.name.as_str()).collect();
+        self.select_registers(&names);
+        for register in registers.iter() {
+            self.pending
+                .insert(register.name.clone(), register.value.clone());
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        if self.registers.is_empty() {
+            let names: Vec<&str> = entry
+                .registers
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect();
+            self.select_registers(&names);
+        }
+        if self.in_range(entry.pc) {
+            let line = self.line(entry);
+            writeln!(self.out, "{}", line)?;
+            self.pending.clear();
+            self.lines += 1;
+        }
+        // the values after the instruction belong to the next line
+        for (name, value) in &entry.registers {
+            self.pending.insert(name.clone(), value.clone());
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Little-endian register bytes as an integer, wider registers truncated
+fn le_value(bytes: &[u8]) -> u64 {
+    let mut value = [0u8; 8];
+    let len = bytes.len().min(8);
+    value[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::MemoryAccess;
+
+    fn entry(pc: u64, registers: &[(&str, u64)], memory: Vec<MemoryAccess>) -> TraceEntry {
+        TraceEntry {
+            index: 0,
+            pc,
+            thread: 1,
+            registers: registers
+                .iter()
+                .map(|&(name, value)| (name.to_owned(), value.to_le_bytes().to_vec()))
+                .collect(),
+            memory,
+        }
+    }
+
+    #[test]
+    fn test_tenet_lines() {
+        let mut writer = TenetWriter::new(Vec::new()).range(0x1000, 0x2000);
+        // push rbp, then a call out of range, then back
+        writer
+            .record(&entry(
+                0x1000,
+                &[("rsp", 0x7ff8), ("rip", 0x1001)],
+                vec![MemoryAccess {
+                    kind: AccessKind::Write,
+                    address: 0x7ff8,
+                    data: vec![0x10, 0x20],
+                }],
+            ))
+            .unwrap();
+        writer
+            .record(&entry(0x1001, &[("rax", 5), ("rip", 0x3000)], vec![]))
+            .unwrap();
+        writer
+            .record(&entry(
+                0x3000,
+                &[("rax", 7), ("rcx", 1), ("rip", 0x1006)],
+                vec![MemoryAccess {
+                    kind: AccessKind::Read,
+                    address: 0x5000,
+                    data: vec![0xff],
+                }],
+            ))
+            .unwrap();
+        writer
+            .record(&entry(0x1006, &[("rip", 0x1007)], vec![]))
+            .unwrap();
+        writer.finish().unwrap();
+        assert_eq!(writer.len(), 3);
+
+        let text = String::from_utf8(writer.into_inner()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "rip=0x1000,mw=0x7ff8:1020",
+                "rsp=0x7ff8,rip=0x1001",
+                "rax=0x7,rcx=0x1,rip=0x1006",
+            ]
+        );
+    }
+}
@@ -0,0 +1,410 @@
+// per-thread access, thread lifecycle tracking and scheduler locking
+
+use crate::{BNDebuggerEventType, DebugFrame, DebugThread, DebuggerController, WeakController};
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Makes a thread active for its lifetime and switches back to the previously
+/// active thread on drop, including while unwinding from a panic.
+///
+/// Derefs to the controller, so `guard.registers()`, `guard.reg_u64("rsp")`,
+/// `guard.arg(0)` etc. all act on the guarded thread.
+pub struct ThreadGuard<'a> {
+    dbg: &'a DebuggerController,
+    thread: DebugThread,
+    previous: DebugThread,
+}
+
+impl ThreadGuard<'_> {
+    pub fn tid(&self) -> u32 {
+        self.thread.tid
+    }
+
+    pub fn thread(&self) -> &DebugThread {
+        &self.thread
+    }
+
+    /// Call stack of the guarded thread
+    pub fn frames(&self) -> Vec<DebugFrame> {
+        self.dbg.frames_of_thread(self.thread.tid)
+    }
+}
+
+impl Deref for ThreadGuard<'_> {
+    type Target = DebuggerController;
+
+    fn deref(&self) -> &DebuggerController {
+        self.dbg
+    }
+}
+
+impl Drop for ThreadGuard<'_> {
+    fn drop(&mut self) {
+        if self.previous.tid != self.thread.tid {
+            // nothing on this path panics, so this is safe during unwinding
+            self.dbg.set_active_thread(&self.previous);
+        }
+    }
+}
+
+impl DebuggerController {
+    /// Temporarily make `tid` the active thread, None if there is no such thread
+    pub fn thread(&self, tid: u32) -> Option<ThreadGuard<'_>> {
+        let thread = self.threads().into_iter().find(|t| t.tid == tid)?;
+        let previous = self.active_thread();
+        if previous.tid != tid {
+            self.set_active_thread(&thread);
+        }
+        Some(ThreadGuard {
+            dbg: self,
+            thread,
+            previous,
+        })
+    }
+
+    /// Run `f` with `tid` active, restoring the previous thread afterwards
+    pub fn with_thread<R>(&self, tid: u32, f: impl FnOnce(&DebuggerController) -> R) -> Option<R> {
+        let guard = self.thread(tid)?;
+        Some(f(&guard))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadEventKind {
+    Created,
+    Exited,
+}
+
+/// A thread appearing or disappearing, as observed at a stop
+#[derive(Debug, Clone)]
+pub struct ThreadEvent {
+    pub tid: u32,
+    pub kind: ThreadEventKind,
+    pub time: SystemTime,
+    /// Index of the stop at which the change was observed
+    pub stop: u64,
+    /// PC of the thread when it was first/last seen
+    pub pc: u64,
+}
+
+impl fmt::Display for ThreadEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            ThreadEventKind::Created => "created",
+            ThreadEventKind::Exited => "exited",
+        };
+        write!(
+            f,
+            "stop {}: thread {} {} @ 0x{:x}",
+            self.stop, self.tid, kind, self.pc
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ThreadRecord {
+    pub tid: u32,
+    /// From `/proc/<pid>/task/<tid>/comm` at the latest update, local targets only
+    pub name: Option<String>,
+    pub created: SystemTime,
+    pub created_stop: u64,
+    pub exited: Option<SystemTime>,
+    pub last_pc: u64,
+    pub is_frozen: bool,
+}
+
+impl ThreadRecord {
+    pub fn is_alive(&self) -> bool {
+        self.exited.is_none()
+    }
+}
+
+impl fmt::Display for ThreadRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Thread {}", self.tid)?;
+        if let Some(name) = &self.name {
+            write!(f, " \"{}\"", name)?;
+        }
+        write!(f, " @ 0x{:x}", self.last_pc)?;
+        if self.exited.is_some() {
+            write!(f, " (exited)")?;
+        } else if self.is_frozen {
+            write!(f, " (frozen)")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct TrackerState {
+    stops: u64,
+    // in creation order
+    threads: Vec<ThreadRecord>,
+    history: Vec<ThreadEvent>,
+}
+
+impl TrackerState {
+    fn update(&mut self, threads: &[DebugThread], pid: Option<u32>) {
+        let now = SystemTime::now();
+        for thread in threads {
+            match self
+                .threads
+                .iter_mut()
+                .find(|r| r.tid == thread.tid && r.is_alive())
+            {
+                Some(record) => {
+                    record.last_pc = thread.rip;
+                    record.is_frozen = thread.is_frozen;
+                    // re-read every time, pthread_setname_np can rename at any point
+                    if let Some(name) = pid.and_then(|pid| thread_name(pid, thread.tid)) {
+                        record.name = Some(name);
+                    }
+                }
+                None => {
+                    self.threads.push(ThreadRecord {
+                        tid: thread.tid,
+                        name: pid.and_then(|pid| thread_name(pid, thread.tid)),
+                        created: now,
+                        created_stop: self.stops,
+                        exited: None,
+                        last_pc: thread.rip,
+                        is_frozen: thread.is_frozen,
+                    });
+                    self.record(thread.tid, ThreadEventKind::Created, now, thread.rip);
+                }
+            }
+        }
+
+        let gone: Vec<(u32, u64)> = self
+            .threads
+            .iter()
+            .filter(|r| r.is_alive() && !threads.iter().any(|t| t.tid == r.tid))
+            .map(|r| (r.tid, r.last_pc))
+            .collect();
+        for (tid, pc) in gone {
+            self.exit(tid, now);
+            self.record(tid, ThreadEventKind::Exited, now, pc);
+        }
+    }
+
+    fn exit(&mut self, tid: u32, time: SystemTime) {
+        if let Some(record) = self
+            .threads
+            .iter_mut()
+            .find(|r| r.tid == tid && r.is_alive())
+        {
+            record.exited = Some(time);
+        }
+    }
+
+    fn exit_all(&mut self) {
+        self.update(&[], None);
+    }
+
+    fn record(&mut self, tid: u32, kind: ThreadEventKind, time: SystemTime, pc: u64) {
+        self.history.push(ThreadEvent {
+            tid,
+            kind,
+            time,
+            stop: self.stops,
+            pc,
+        });
+    }
+}
+
+fn thread_name(pid: u32, tid: u32) -> Option<String> {
+    let comm = std::fs::read_to_string(format!("/proc/{}/task/{}/comm", pid, tid)).ok()?;
+    Some(comm.trim_end().to_owned())
+}
+
+/// Keeps the history of every thread of the target, see `DebuggerController::track_threads`
+pub struct ThreadTracker {
+    dbg: WeakController,
+    callback: usize,
+    state: Arc<Mutex<TrackerState>>,
+}
+
+impl ThreadTracker {
+    /// All threads seen so far in creation order, including exited ones
+    pub fn threads(&self) -> Vec<ThreadRecord> {
+        self.state.lock().unwrap().threads.clone()
+    }
+
+    pub fn alive(&self) -> Vec<ThreadRecord> {
+        self.threads()
+            .into_iter()
+            .filter(|r| r.is_alive())
+            .collect()
+    }
+
+    /// Latest record for a tid, tids can be reused after a thread exits
+    pub fn get(&self, tid: u32) -> Option<ThreadRecord> {
+        let state = self.state.lock().unwrap();
+        state.threads.iter().rev().find(|r| r.tid == tid).cloned()
+    }
+
+    /// Creation and exit events, oldest first
+    pub fn history(&self) -> Vec<ThreadEvent> {
+        self.state.lock().unwrap().history.clone()
+    }
+
+    /// Re-read the thread list now instead of waiting for the next event
+    pub fn refresh(&self) {
+        if let Some(dbg) = self.dbg.upgrade() {
+            refresh(&dbg, &self.state);
+        }
+    }
+}
+
+impl Drop for ThreadTracker {
+    fn drop(&mut self) {
+        if let Some(dbg) = self.dbg.upgrade() {
+            dbg.remove_event_callback(self.callback);
+        }
+    }
+}
+
+fn refresh(dbg: &DebuggerController, state: &Mutex<TrackerState>) {
+    let threads = dbg.threads();
+    let pid = dbg.is_local_target().then(|| dbg.active_pid());
+    state.lock().unwrap().update(&threads, pid);
+}
+
+impl DebuggerController {
+    /// Start tracking thread creation and exit.
+    ///
+    /// The thread list is diffed on every stop, thread state change and
+    /// active thread switch, so creation/exit times are those of the first
+    /// stop at which the change was visible. Tracking stops when the tracker
+    /// is dropped.
+    pub fn track_threads(&self) -> ThreadTracker {
+        let state = Arc::new(Mutex::new(TrackerState::default()));
+        if self.is_connected() {
+            refresh(self, &state);
+        }
+
+        let dbg = self.downgrade();
+        let events = Arc::clone(&state);
+        let callback = self.register_event_callback("rust-thread-tracker", move |event| {
+            use BNDebuggerEventType::*;
+            match event.event_type {
+                TargetStoppedEventType => {
+                    events.lock().unwrap().stops += 1;
+                }
+                ThreadStateChangedEvent | ActiveThreadChangedEvent => {}
+                TargetExitedEventType | DetachedEventType => {
+                    events.lock().unwrap().exit_all();
+                    return;
+                }
+                _ => return,
+            }
+            if let Some(dbg) = dbg.upgrade() {
+                refresh(&dbg, &events);
+            }
+        });
+
+        ThreadTracker {
+            dbg: self.downgrade(),
+            callback,
+            state,
+        }
+    }
+}
+
+/// Whether other threads are suspended while the active thread steps or runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[repr(u8)]
+pub enum SchedulerLock {
+    /// All threads run
+    #[default]
+    Off = 0,
+    /// Other threads are suspended during `step_*_and_wait`
+    Step = 1,
+    /// Other threads are suspended during `step_*_and_wait` and `run_to_and_wait`
+    On = 2,
+}
+
+impl SchedulerLock {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            1 => Self::Step,
+            2 => Self::On,
+            _ => Self::Off,
+        }
+    }
+}
+
+/// Threads suspended for one step/run_to, resumed on drop even if the
+/// operation failed or panicked
+pub(crate) struct SchedulerGuard<'a> {
+    dbg: &'a DebuggerController,
+    suspended: Vec<u32>,
+}
+
+impl Drop for SchedulerGuard<'_> {
+    fn drop(&mut self) {
+        for &tid in &self.suspended {
+            if !self.dbg.resume_thread(tid) {
+                log::warn!("failed to resume thread {}", tid);
+            }
+        }
+    }
+}
+
+impl DebuggerController {
+    /// Set the scheduler locking mode used by the blocking step and run_to calls.
+    ///
+    /// The non-blocking `step_*` and `run_to` do not lock and log a warning
+    /// when a mode applying to them is set.
+    pub fn set_scheduler_lock(&self, mode: SchedulerLock) {
+        self.inner
+            .scheduler_lock
+            .store(mode as u8, Ordering::Release);
+    }
+
+    pub fn scheduler_lock(&self) -> SchedulerLock {
+        SchedulerLock::from_u8(self.inner.scheduler_lock.load(Ordering::Acquire))
+    }
+
+    /// Warn that a non-blocking call ignores the scheduler lock. The threads
+    /// would have to stay suspended until a stop nobody waits for.
+    pub(crate) fn warn_scheduler_unlocked(&self, call: &str, required: SchedulerLock) {
+        let mode = self.scheduler_lock();
+        if mode >= required {
+            log::warn!(
+                "{} ignores scheduler lock {:?}, other threads keep running; use {}_and_wait",
+                call,
+                mode,
+                call
+            );
+        }
+    }
+
+    /// Suspend every thread but the active one if the mode is at least `required`.
+    ///
+    /// Threads the user already froze are left alone and stay frozen afterwards.
+    pub(crate) fn lock_scheduler(&self, required: SchedulerLock) -> Option<SchedulerGuard<'_>> {
+        if self.scheduler_lock() < required {
+            return None;
+        }
+        let active = self.active_thread().tid;
+        let mut guard = SchedulerGuard {
+            dbg: self,
+            suspended: Vec::new(),
+        };
+        for thread in self.threads() {
+            if thread.tid == active || thread.is_frozen {
+                continue;
+            }
+            if self.suspend_thread(thread.tid) {
+                guard.suspended.push(thread.tid);
+            } else {
+                log::warn!("failed to suspend thread {}", thread.tid);
+            }
+        }
+        Some(guard)
+    }
+}
@@ -0,0 +1,914 @@
+// instruction traces: recording by single-stepping, and a compact seekable file format
+
+use crate::lift::Lifter;
+use crate::registers::{RegisterSnapshot, RegisterValue};
+use crate::types::{CoreFunction, CoreView};
+use crate::{BNDebugStopReason, BNFunctionGraphType, DebuggerController};
+use binaryninjacore_sys as sys;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// How far the recorder runs the target between two trace entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceMode {
+    /// One entry per instruction, with its memory accesses
+    #[default]
+    Instruction,
+    /// One entry per basic block of the analysis, with the registers changed by
+    /// the whole block and no memory accesses. Calls inside a block run to
+    /// completion as part of it. Code without analysis is single-stepped.
+    BasicBlock,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// Memory accessed by a traced instruction, the value read before or
+/// written after it executed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub address: u64,
+    pub data: Vec<u8>,
+}
+
+impl fmt::Display for MemoryAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            AccessKind::Read => 'R',
+            AccessKind::Write => 'W',
+        };
+        write!(f, "{} [0x{:x}] {:02x?}", kind, self.address, self.data)
+    }
+}
+
+/// One executed instruction, or basic block in `TraceMode::BasicBlock`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub index: u64,
+    pub pc: u64,
+    pub thread: u32,
+    /// New values of the registers it changed
+    pub registers: Vec<(String, Vec<u8>)>,
+    pub memory: Vec<MemoryAccess>,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} [{}] 0x{:x}", self.index, self.thread, self.pc)?;
+        for (name, value) in &self.registers {
+            match RegisterValue::from_bytes(value) {
+                Some(value) => write!(f, " {}={}", name, value)?,
+                None => write!(f, " {}={:02x?}", name, value)?,
+            }
+        }
+        for access in &self.memory {
+            write!(f, " {}", access)?;
+        }
+        Ok(())
+    }
+}
+
+/// Receives the entries of a recording
+pub trait TraceSink {
+    /// Called once before the first entry with the full register file
+    fn begin(&mut self, registers: &RegisterSnapshot) -> io::Result<()> {
+        let _ = registers;
+        Ok(())
+    }
+
+    fn record(&mut self, entry: &TraceEntry) -> io::Result<()>;
+
+    /// Called once after the last entry, also when the recording was cancelled
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl TraceSink for Vec<TraceEntry> {
+    fn record(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        self.push(entry.clone());
+        Ok(())
+    }
+}
+
+/// Why a recording ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceStop {
+    /// The target reached one of the end addresses
+    Reached(u64),
+    /// The entry limit was reached
+    Limit,
+    Cancelled,
+    /// The target stopped for another reason, e.g. it exited or faulted
+    Stopped(BNDebugStopReason),
+}
+
+impl fmt::Display for TraceStop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Reached(address) => write!(f, "reached 0x{:x}", address),
+            Self::Limit => write!(f, "limit reached"),
+            Self::Cancelled => write!(f, "cancelled"),
+            Self::Stopped(reason) => write!(f, "stopped: {:?}", reason),
+        }
+    }
+}
+
+/// Number of entries between two progress reports
+const PROGRESS_INTERVAL: u64 = 1000;
+
+/// Records the execution of the active thread into a `TraceSink`, see
+/// `DebuggerController::trace_recorder`
+pub struct TraceRecorder<'a> {
+    dbg: &'a DebuggerController,
+    mode: TraceMode,
+    start: Option<u64>,
+    end: Vec<u64>,
+    limit: Option<u64>,
+    memory: bool,
+    cancel: Arc<AtomicBool>,
+    progress: Option<Box<dyn FnMut(u64, u64) + 'a>>,
+}
+
+impl<'a> TraceRecorder<'a> {
+    pub fn mode(mut self, mode: TraceMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Run to `address` before recording
+    pub fn from(mut self, address: u64) -> Self {
+        self.start = Some(address);
+        self
+    }
+
+    /// Stop once the target reaches `address`, can be given several times
+    pub fn until(mut self, address: u64) -> Self {
+        self.end.push(address);
+        self
+    }
+
+    /// Stop after `count` entries
+    pub fn limit(mut self, count: u64) -> Self {
+        self.limit = Some(count);
+        self
+    }
+
+    /// Record memory accesses, on by default. Decoding them costs a memory
+    /// read per access before and after each step.
+    pub fn memory(mut self, enabled: bool) -> Self {
+        self.memory = enabled;
+        self
+    }
+
+    /// Called with the number of entries and the current pc every
+    /// `PROGRESS_INTERVAL` entries
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(u64, u64) + 'a,
+    {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
+    /// Set from any thread to stop the recording after the current step
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.cancel)
+    }
+
+    /// Record until an end address, the limit, cancellation or any other stop
+    /// of the target. Sink errors abort the recording. When a step stops on
+    /// another thread the recording follows that thread.
+    pub fn record(mut self, sink: &mut dyn TraceSink) -> io::Result<TraceStop> {
+        let dbg = self.dbg;
+        if let Some(start) = self.start.filter(|&start| dbg.ip() != start) {
+            let reason = dbg.run_to_and_wait(&[start]);
+            if dbg.ip() != start {
+                log::warn!("target stopped before reaching 0x{:x}", start);
+                return Ok(TraceStop::Stopped(reason));
+            }
+        }
+        let Some(arch) = dbg.arch() else {
+            log::warn!("cannot trace without a target architecture");
+            return Ok(TraceStop::Stopped(dbg.stop_reason()));
+        };
+        let mut lifter = Lifter::new(arch);
+        let address_size = dbg.address_size();
+        let decode_memory = self.memory && self.mode == TraceMode::Instruction;
+
+        let mut previous = dbg.registers_snapshot();
+        sink.begin(&previous)?;
+        let mut count = 0u64;
+        let stop = loop {
+            if self.cancel.load(Ordering::Relaxed) {
+                break TraceStop::Cancelled;
+            }
+            if self.limit.is_some_and(|limit| count >= limit) {
+                break TraceStop::Limit;
+            }
+            let pc = dbg.ip();
+            let thread = dbg.active_thread().tid;
+            let sp = dbg.stack_pointer();
+            let (planned, is_call) = if decode_memory {
+                (lifter.accesses(dbg, pc, &previous), lifter.is_call(dbg, pc))
+            } else {
+                (Vec::new(), false)
+            };
+            let mut memory: Vec<MemoryAccess> = planned
+                .iter()
+                .filter(|access| !access.write)
+                .filter_map(|access| {
+                    Some(MemoryAccess {
+                        kind: AccessKind::Read,
+                        address: access.address,
+                        data: dbg.read_memory(access.address, access.size)?,
+                    })
+                })
+                .collect();
+
+            let reason = match self.mode {
+                TraceMode::Instruction => {
+                    dbg.step_into_and_wait(BNFunctionGraphType::NormalFunctionGraph)
+                }
+                TraceMode::BasicBlock => self.step_block(pc),
+            };
+            if !matches!(
+                reason,
+                BNDebugStopReason::SingleStep | BNDebugStopReason::Breakpoint
+            ) {
+                break TraceStop::Stopped(reason);
+            }
+            // stopped on another thread, its registers are no diff of the last entry
+            if dbg.active_thread().tid != thread {
+                log::debug!("trace continues on thread {}", dbg.active_thread().tid);
+                previous = dbg.registers_snapshot();
+                continue;
+            }
+
+            let current = dbg.registers_snapshot();
+            memory.extend(
+                planned
+                    .iter()
+                    .filter(|access| access.write)
+                    .filter_map(|access| {
+                        Some(MemoryAccess {
+                            kind: AccessKind::Write,
+                            address: access.address,
+                            data: dbg.read_memory(access.address, access.size)?,
+                        })
+                    }),
+            );
+            // the return address pushed by a call
+            let new_sp = dbg.stack_pointer();
+            if is_call && new_sp.wrapping_add(address_size as u64) == sp {
+                if let Some(data) = dbg.read_memory(new_sp, address_size) {
+                    memory.push(MemoryAccess {
+                        kind: AccessKind::Write,
+                        address: new_sp,
+                        data,
+                    });
+                }
+            }
+            let registers = RegisterSnapshot::diff(&previous, &current)
+                .into_iter()
+                .filter_map(|change| Some((change.name, change.new?)))
+                .collect();
+            sink.record(&TraceEntry {
+                index: count,
+                pc,
+                thread,
+                registers,
+                memory,
+            })?;
+            count += 1;
+            previous = current;
+
+            let pc = dbg.ip();
+            if count.is_multiple_of(PROGRESS_INTERVAL) {
+                if let Some(progress) = &mut self.progress {
+                    progress(count, pc);
+                }
+            }
+            if self.end.contains(&pc) {
+                break TraceStop::Reached(pc);
+            }
+        };
+        sink.finish()?;
+        log::info!(
+            "traced {} entries up to {}, {}",
+            count,
+            dbg.format_address(dbg.ip()),
+            stop
+        );
+        Ok(stop)
+    }
+
+    /// Run to the last instruction of the basic block at `pc` and step over
+    /// it, or only run to the first end address inside the block
+    fn step_block(&self, pc: u64) -> BNDebugStopReason {
+        let last = self
+            .dbg
+            .analysis_view(pc)
+            .and_then(|view| last_instruction(&view, pc));
+        if let Some(end) = last.and_then(|last| {
+            self.end
+                .iter()
+                .copied()
+                .filter(|&end| end > pc && end <= last)
+                .min()
+        }) {
+            return self.dbg.run_to_and_wait(&[end]);
+        }
+        if let Some(last) = last.filter(|&last| last != pc) {
+            let reason = self.dbg.run_to_and_wait(&[last]);
+            if self.dbg.ip() != last {
+                return reason;
+            }
+        }
+        self.dbg
+            .step_into_and_wait(BNFunctionGraphType::NormalFunctionGraph)
+    }
+}
+
+/// Address of the last instruction of the basic block containing `pc`
+fn last_instruction(view: &CoreView, pc: u64) -> Option<u64> {
+    let func = CoreFunction::at(view, pc)?;
+    unsafe {
+        let arch = func.arch();
+        let block = sys::BNGetFunctionBasicBlockAtAddress(func.handle(), arch, pc);
+        if block.is_null() {
+            return None;
+        }
+        let end = sys::BNGetBasicBlockEnd(block);
+        sys::BNFreeBasicBlock(block);
+        let mut address = pc;
+        loop {
+            let len = sys::BNGetInstructionLength(view.handle(), arch, address) as u64;
+            if len == 0 {
+                return None;
+            }
+            if address + len >= end {
+                return Some(address);
+            }
+            address += len;
+        }
+    }
+}
+
+impl DebuggerController {
+    /// Recorder for the active thread, single-stepping until `until`, `limit`
+    /// or the target stops on its own. Pass a `TraceWriter` to `record` to
+    /// save the trace to a file, or a `Vec<TraceEntry>` to keep it in memory.
+    pub fn trace_recorder(&self) -> TraceRecorder<'_> {
+        TraceRecorder {
+            dbg: self,
+            mode: TraceMode::default(),
+            start: None,
+            end: Vec::new(),
+            limit: None,
+            memory: true,
+            cancel: Arc::new(AtomicBool::new(false)),
+            progress: None,
+        }
+    }
+}
+
+// File layout, integers are LEB128 unless noted:
+//
+//   header   MAGIC, initial register count, (register id, length, bytes)...
+//   records  flags u8, pc delta (signed), [thread if flags & THREAD_CHANGED],
+//            register count, (id, length, bytes)..., access count,
+//            (kind u8, address, length, bytes)...
+//   index    register names, checkpoints every CHECKPOINT_INTERVAL records
+//            (offset, previous pc, thread), pcs (pc, count, index deltas...)
+//   trailer  record count u64, index offset u64, INDEX_MAGIC
+
+const MAGIC: &[u8; 8] = b"BNTRACE\x01";
+const INDEX_MAGIC: &[u8; 8] = b"BNTRIDX\x01";
+const TRAILER_SIZE: u64 = 24;
+/// Records between two seek points of the index
+pub const CHECKPOINT_INTERVAL: u64 = 1024;
+const THREAD_CHANGED: u8 = 1;
+
+fn put_uleb(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn put_sleb(out: &mut Vec<u8>, value: i64) {
+    // zigzag, small deltas in either direction stay short
+    put_uleb(out, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_uleb(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+fn get_u8(input: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0u8];
+    input.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn get_uleb(input: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = get_u8(input)?;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("LEB128 value too long"))
+}
+
+fn get_sleb(input: &mut impl Read) -> io::Result<i64> {
+    let value = get_uleb(input)?;
+    Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+}
+
+fn get_bytes(input: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = get_uleb(input)?;
+    let mut bytes = Vec::new();
+    input.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+/// Position of the decoder between records
+#[derive(Debug, Clone, Copy, Default)]
+struct Checkpoint {
+    offset: u64,
+    previous_pc: u64,
+    thread: u32,
+}
+
+/// Writes the binary trace format, see `TraceReader`
+pub struct TraceWriter<W: Write> {
+    out: W,
+    position: u64,
+    started: bool,
+    names: Vec<String>,
+    ids: HashMap<String, u64>,
+    count: u64,
+    state: Checkpoint,
+    checkpoints: Vec<Checkpoint>,
+    pcs: BTreeMap<u64, Vec<u64>>,
+}
+
+impl TraceWriter<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            position: 0,
+            started: false,
+            names: Vec::new(),
+            ids: HashMap::new(),
+            count: 0,
+            state: Checkpoint::default(),
+            checkpoints: Vec::new(),
+            pcs: BTreeMap::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.position += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn register_id(&mut self, name: &str) -> u64 {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = self.names.len() as u64;
+        self.names.push(name.to_owned());
+        self.ids.insert(name.to_owned(), id);
+        id
+    }
+
+    fn write_header(&mut self, registers: &[(&str, &[u8])]) -> io::Result<()> {
+        let mut out = MAGIC.to_vec();
+        put_uleb(&mut out, registers.len() as u64);
+        for (name, value) in registers {
+            put_uleb(&mut out, self.register_id(name));
+            put_bytes(&mut out, value);
+        }
+        self.started = true;
+        self.write(&out)
+    }
+}
+
+impl<W: Write> TraceSink for TraceWriter<W> {
+    fn begin(&mut self, registers: &RegisterSnapshot) -> io::Result<()> {
+        let registers: Vec<(&str, &[u8])> = registers
+            .iter()
+            .map(|r| (r.name.as_str(), r.value.as_slice()))
+            .collect();
+        self.write_header(&registers)
+    }
+
+    fn record(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        if !self.started {
+            self.write_header(&[])?;
+        }
+        if self.count.is_multiple_of(CHECKPOINT_INTERVAL) {
+            self.checkpoints.push(Checkpoint {
+                offset: self.position,
+                ..self.state
+            });
+        }
+        let thread_changed = entry.thread != self.state.thread;
+        let mut out = vec![if thread_changed { THREAD_CHANGED } else { 0 }];
+        put_sleb(
+            &mut out,
+            entry.pc.wrapping_sub(self.state.previous_pc) as i64,
+        );
+        if thread_changed {
+            put_uleb(&mut out, entry.thread as u64);
+        }
+        put_uleb(&mut out, entry.registers.len() as u64);
+        for (name, value) in &entry.registers {
+            put_uleb(&mut out, self.register_id(name));
+            put_bytes(&mut out, value);
+        }
+        put_uleb(&mut out, entry.memory.len() as u64);
+        for access in &entry.memory {
+            out.push(access.kind as u8);
+            put_uleb(&mut out, access.address);
+            put_bytes(&mut out, &access.data);
+        }
+        self.write(&out)?;
+
+        self.pcs.entry(entry.pc).or_default().push(self.count);
+        self.state.previous_pc = entry.pc;
+        self.state.thread = entry.thread;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.write_header(&[])?;
+        }
+        let index_offset = self.position;
+        let mut out = Vec::new();
+        put_uleb(&mut out, self.names.len() as u64);
+        for name in &self.names {
+            put_bytes(&mut out, name.as_bytes());
+        }
+        put_uleb(&mut out, self.checkpoints.len() as u64);
+        for checkpoint in &self.checkpoints {
+            put_uleb(&mut out, checkpoint.offset);
+            put_uleb(&mut out, checkpoint.previous_pc);
+            put_uleb(&mut out, checkpoint.thread as u64);
+        }
+        put_uleb(&mut out, self.pcs.len() as u64);
+        for (&pc, indices) in &self.pcs {
+            put_uleb(&mut out, pc);
+            put_uleb(&mut out, indices.len() as u64);
+            let mut previous = 0;
+            for &index in indices {
+                put_uleb(&mut out, index - previous);
+                previous = index;
+            }
+        }
+        out.extend_from_slice(&self.count.to_le_bytes());
+        out.extend_from_slice(&index_offset.to_le_bytes());
+        out.extend_from_slice(INDEX_MAGIC);
+        self.write(&out)?;
+        self.out.flush()
+    }
+}
+
+/// Reads traces written by `TraceWriter`, iterating from any record and
+/// looking records up by pc through the index at the end of the file
+pub struct TraceReader<R: Read + Seek> {
+    input: R,
+    len: u64,
+    names: Vec<String>,
+    initial: Vec<(String, Vec<u8>)>,
+    checkpoints: Vec<Checkpoint>,
+    pcs: HashMap<u64, Vec<u64>>,
+}
+
+impl TraceReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> TraceReader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let size = input.seek(SeekFrom::End(0))?;
+        if size < MAGIC.len() as u64 + TRAILER_SIZE {
+            return Err(invalid("not a trace file"));
+        }
+        input.seek(SeekFrom::Start(size - TRAILER_SIZE))?;
+        let mut trailer = [0u8; TRAILER_SIZE as usize];
+        input.read_exact(&mut trailer)?;
+        if &trailer[16..] != INDEX_MAGIC {
+            return Err(invalid("trace file has no index, it was not finished"));
+        }
+        let len = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+        let index_offset = u64::from_le_bytes(trailer[8..16].try_into().unwrap());
+        if index_offset > size - TRAILER_SIZE {
+            return Err(invalid("trace index out of bounds"));
+        }
+
+        input.seek(SeekFrom::Start(index_offset))?;
+        let mut index = (&mut input).take(size - TRAILER_SIZE - index_offset);
+        let names = (0..get_uleb(&mut index)?)
+            .map(|_| Ok(String::from_utf8_lossy(&get_bytes(&mut index)?).into_owned()))
+            .collect::<io::Result<Vec<_>>>()?;
+        let checkpoints = (0..get_uleb(&mut index)?)
+            .map(|_| {
+                Ok(Checkpoint {
+                    offset: get_uleb(&mut index)?,
+                    previous_pc: get_uleb(&mut index)?,
+                    thread: get_uleb(&mut index)? as u32,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut pcs = HashMap::new();
+        for _ in 0..get_uleb(&mut index)? {
+            let pc = get_uleb(&mut index)?;
+            let mut previous = 0;
+            let indices = (0..get_uleb(&mut index)?)
+                .map(|_| {
+                    previous += get_uleb(&mut index)?;
+                    Ok(previous)
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+            pcs.insert(pc, indices);
+        }
+
+        input.seek(SeekFrom::Start(0))?;
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a trace file"));
+        }
+        let initial = (0..get_uleb(&mut input)?)
+            .map(|_| {
+                let id = get_uleb(&mut input)?;
+                let name = names
+                    .get(id as usize)
+                    .ok_or_else(|| invalid("bad register id"))?;
+                Ok((name.clone(), get_bytes(&mut input)?))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            input,
+            len,
+            names,
+            initial,
+            checkpoints,
+            pcs,
+        })
+    }
+
+    /// Number of records
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The full register file before the first record
+    pub fn initial_registers(&self) -> &[(String, Vec<u8>)] {
+        &self.initial
+    }
+
+    /// Indices of the records executed at `pc`, in order
+    pub fn indices_at(&self, pc: u64) -> &[u64] {
+        self.pcs.get(&pc).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Every record executed at `pc`
+    pub fn entries_at(&mut self, pc: u64) -> io::Result<Vec<TraceEntry>> {
+        let indices = self.indices_at(pc).to_vec();
+        indices
+            .into_iter()
+            .filter_map(|index| self.get(index).transpose())
+            .collect()
+    }
+
+    /// Record `index`, decoding at most `CHECKPOINT_INTERVAL` records
+    pub fn get(&mut self, index: u64) -> io::Result<Option<TraceEntry>> {
+        self.iter_from(index).next().transpose()
+    }
+
+    pub fn iter(&mut self) -> TraceIter<'_, R> {
+        self.iter_from(0)
+    }
+
+    /// Iterate from record `index` to the end
+    pub fn iter_from(&mut self, index: u64) -> TraceIter<'_, R> {
+        let checkpoint = self
+            .checkpoints
+            .get((index / CHECKPOINT_INTERVAL) as usize)
+            .copied();
+        TraceIter {
+            skip: index % CHECKPOINT_INTERVAL,
+            index: index / CHECKPOINT_INTERVAL * CHECKPOINT_INTERVAL,
+            state: checkpoint.unwrap_or_default(),
+            seeked: false,
+            done: checkpoint.is_none() || index >= self.len,
+            reader: self,
+        }
+    }
+
+    fn read_record(&mut self, index: u64, state: &mut Checkpoint) -> io::Result<TraceEntry> {
+        let input = &mut self.input;
+        let flags = get_u8(input)?;
+        let pc = state.previous_pc.wrapping_add(get_sleb(input)? as u64);
+        if flags & THREAD_CHANGED != 0 {
+            state.thread = get_uleb(input)? as u32;
+        }
+        let registers = (0..get_uleb(input)?)
+            .map(|_| {
+                let id = get_uleb(input)?;
+                let name = self
+                    .names
+                    .get(id as usize)
+                    .ok_or_else(|| invalid("bad register id"))?;
+                Ok((name.clone(), get_bytes(input)?))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let memory = (0..get_uleb(input)?)
+            .map(|_| {
+                let kind = match get_u8(input)? {
+                    0 => AccessKind::Read,
+                    1 => AccessKind::Write,
+                    _ => return Err(invalid("bad memory access kind")),
+                };
+                Ok(MemoryAccess {
+                    kind,
+                    address: get_uleb(input)?,
+                    data: get_bytes(input)?,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        state.previous_pc = pc;
+        Ok(TraceEntry {
+            index,
+            pc,
+            thread: state.thread,
+            registers,
+            memory,
+        })
+    }
+}
+
+/// Records of a `TraceReader` in order, see `TraceReader::iter_from`
+pub struct TraceIter<'a, R: Read + Seek> {
+    reader: &'a mut TraceReader<R>,
+    index: u64,
+    /// Records to decode and drop before the first one returned
+    skip: u64,
+    state: Checkpoint,
+    seeked: bool,
+    done: bool,
+}
+
+impl<R: Read + Seek> Iterator for TraceIter<'_, R> {
+    type Item = io::Result<TraceEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if !self.seeked {
+            self.seeked = true;
+            if let Err(err) = self.reader.input.seek(SeekFrom::Start(self.state.offset)) {
+                self.done = true;
+                return Some(Err(err));
+            }
+        }
+        loop {
+            if self.index >= self.reader.len {
+                self.done = true;
+                return None;
+            }
+            let entry = self.reader.read_record(self.index, &mut self.state);
+            self.index += 1;
+            match entry {
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+                Ok(_) if self.skip > 0 => self.skip -= 1,
+                Ok(entry) => return Some(Ok(entry)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn entry(index: u64) -> TraceEntry {
+        let pc = 0x401000 + (index % 7) * 4;
+        TraceEntry {
+            index,
+            pc,
+            thread: if index < 1500 { 100 } else { 101 },
+            registers: vec![
+                ("rip".to_owned(), pc.to_le_bytes().to_vec()),
+                ("eflags".to_owned(), (index as u32).to_le_bytes().to_vec()),
+            ],
+            memory: if index.is_multiple_of(3) {
+                vec![MemoryAccess {
+                    kind: AccessKind::Write,
+                    address: 0x7ffe_0000 - index * 8,
+                    data: index.to_le_bytes().to_vec(),
+                }]
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    #[test]
+    fn test_trace_roundtrip() {
+        let entries: Vec<TraceEntry> = (0..3000).map(entry).collect();
+        let mut writer = TraceWriter::new(Cursor::new(Vec::new()));
+        writer
+            .write_header(&[("rsp", &0x7ffe_0000u64.to_le_bytes())])
+            .unwrap();
+        for entry in &entries {
+            writer.record(entry).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut reader = TraceReader::new(writer.into_inner()).unwrap();
+        assert_eq!(reader.len(), 3000);
+        assert_eq!(
+            reader.initial_registers(),
+            &[("rsp".to_owned(), 0x7ffe_0000u64.to_le_bytes().to_vec())]
+        );
+        let read: Vec<TraceEntry> = reader.iter().collect::<io::Result<_>>().unwrap();
+        assert_eq!(read, entries);
+
+        // seeking through checkpoints
+        assert_eq!(reader.get(2049).unwrap(), Some(entries[2049].clone()));
+        assert_eq!(reader.get(1023).unwrap(), Some(entries[1023].clone()));
+        assert_eq!(reader.get(3000).unwrap(), None);
+        let tail: Vec<TraceEntry> = reader.iter_from(2990).collect::<io::Result<_>>().unwrap();
+        assert_eq!(tail, entries[2990..]);
+
+        let at_pc = reader.indices_at(0x401008).to_vec();
+        assert_eq!(
+            at_pc.len(),
+            entries.iter().filter(|e| e.pc == 0x401008).count()
+        );
+        assert!(at_pc.iter().all(|&i| entries[i as usize].pc == 0x401008));
+        assert_eq!(reader.entries_at(0x401008).unwrap().len(), at_pc.len());
+    }
+
+    #[test]
+    fn test_unfinished_trace() {
+        let mut writer = TraceWriter::new(Cursor::new(Vec::new()));
+        writer.record(&entry(0)).unwrap();
+        assert!(TraceReader::new(writer.into_inner()).is_err());
+    }
+}
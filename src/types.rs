@@ -0,0 +1,438 @@
+// thin owned wrapper around core types, used to annotate and decode target memory
+
+use crate::abi::CallingConvention;
+use binaryninjacore_sys as sys;
+use binaryninjacore_sys::{BNRegisterValueType, BNTokenEscapingType, BNTypeClass};
+use std::ffi::{c_char, CStr};
+
+/// Take ownership of a core allocated string
+pub(crate) unsafe fn core_string(ptr: *mut c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    let s = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+    sys::BNFreeString(ptr);
+    Some(s)
+}
+
+pub(crate) struct CoreType {
+    handle: *mut sys::BNType,
+}
+
+impl Drop for CoreType {
+    fn drop(&mut self) {
+        unsafe { sys::BNFreeType(self.handle) }
+    }
+}
+
+impl CoreType {
+    /// Wrap an owned type reference, returns None for null
+    pub(crate) unsafe fn from_raw(handle: *mut sys::BNType) -> Option<Self> {
+        if handle.is_null() {
+            None
+        } else {
+            Some(Self { handle })
+        }
+    }
+
+    /// Wrap a borrowed type reference, taking a new reference to it
+    pub(crate) unsafe fn from_borrowed(handle: *mut sys::BNType) -> Option<Self> {
+        if handle.is_null() {
+            None
+        } else {
+            Self::from_raw(sys::BNNewTypeReference(handle))
+        }
+    }
+
+    pub(crate) fn handle(&self) -> *mut sys::BNType {
+        self.handle
+    }
+
+    pub(crate) fn class(&self) -> BNTypeClass {
+        unsafe { sys::BNGetTypeClass(self.handle) }
+    }
+
+    pub(crate) fn width(&self) -> u64 {
+        unsafe { sys::BNGetTypeWidth(self.handle) }
+    }
+
+    pub(crate) fn is_signed(&self) -> bool {
+        unsafe { sys::BNIsTypeSigned(self.handle).value }
+    }
+
+    pub(crate) fn name(&self) -> String {
+        unsafe {
+            core_string(sys::BNGetTypeString(
+                self.handle,
+                std::ptr::null_mut(),
+                BNTokenEscapingType::NoTokenEscapingType,
+            ))
+            .unwrap_or_default()
+        }
+    }
+
+    /// Pointer target or array element type
+    pub(crate) fn child(&self) -> Option<CoreType> {
+        unsafe { Self::from_raw(sys::BNGetChildType(self.handle).type_) }
+    }
+
+    pub(crate) fn element_count(&self) -> u64 {
+        unsafe { sys::BNGetTypeElementCount(self.handle) }
+    }
+
+    /// Follow named type references (typedefs, `struct Foo`) to the definition in the view
+    pub(crate) fn resolve(self, view: *mut sys::BNBinaryView) -> CoreType {
+        let mut ty = self;
+        // bounded, typedef chains can be cyclic in broken databases
+        for _ in 0..16 {
+            if ty.class() != BNTypeClass::NamedTypeReferenceClass {
+                break;
+            }
+            let resolved = unsafe {
+                let ntr = sys::BNGetTypeNamedTypeReference(ty.handle);
+                if ntr.is_null() {
+                    break;
+                }
+                let resolved = sys::BNGetAnalysisTypeByRef(view, ntr);
+                sys::BNFreeNamedTypeReference(ntr);
+                Self::from_raw(resolved)
+            };
+            match resolved {
+                Some(r) => ty = r,
+                None => break,
+            }
+        }
+        ty
+    }
+
+    /// Structure members as (name, offset, type)
+    pub(crate) fn members(&self) -> Vec<(String, u64, CoreType)> {
+        let mut result = Vec::new();
+        unsafe {
+            let structure = sys::BNGetTypeStructure(self.handle);
+            if structure.is_null() {
+                return result;
+            }
+            let mut count = 0usize;
+            let ptr = sys::BNGetStructureMembers(structure, &mut count);
+            if !ptr.is_null() {
+                for m in std::slice::from_raw_parts(ptr, count) {
+                    let name = if m.name.is_null() {
+                        String::new()
+                    } else {
+                        CStr::from_ptr(m.name).to_string_lossy().into_owned()
+                    };
+                    if let Some(ty) = Self::from_borrowed(m.type_) {
+                        result.push((name, m.offset, ty));
+                    }
+                }
+                sys::BNFreeStructureMemberList(ptr, count);
+            }
+            sys::BNFreeStructure(structure);
+        }
+        result
+    }
+
+    /// Describe the field at `offset` inside this type, e.g. `header.entries[3].len`
+    pub(crate) fn field_path(self, view: *mut sys::BNBinaryView, offset: u64) -> Option<String> {
+        let ty = self.resolve(view);
+        match ty.class() {
+            BNTypeClass::StructureTypeClass => {
+                let (name, member_offset, member_ty) =
+                    ty.members().into_iter().rev().find(|(_, off, mty)| {
+                        offset >= *off && offset < off + mty.width().max(1)
+                    })?;
+                match member_ty.field_path(view, offset - member_offset) {
+                    Some(sub) if sub.starts_with('[') => Some(format!("{}{}", name, sub)),
+                    Some(sub) => Some(format!("{}.{}", name, sub)),
+                    None => Some(name),
+                }
+            }
+            BNTypeClass::ArrayTypeClass => {
+                let element = ty.child()?;
+                let stride = element.width().max(1);
+                let index = offset / stride;
+                if index >= ty.element_count() {
+                    return None;
+                }
+                match element.field_path(view, offset % stride) {
+                    Some(sub) if sub.starts_with('[') => Some(format!("[{}]{}", index, sub)),
+                    Some(sub) => Some(format!("[{}].{}", index, sub)),
+                    None => Some(format!("[{}]", index)),
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Owned binary view reference, freed on drop
+pub(crate) struct CoreView {
+    handle: *mut sys::BNBinaryView,
+}
+
+impl Drop for CoreView {
+    fn drop(&mut self) {
+        unsafe { sys::BNFreeBinaryView(self.handle) }
+    }
+}
+
+impl Clone for CoreView {
+    fn clone(&self) -> Self {
+        Self {
+            handle: unsafe { sys::BNNewViewReference(self.handle) },
+        }
+    }
+}
+
+impl CoreView {
+    /// Wrap an owned view reference, returns None for null
+    pub(crate) unsafe fn from_raw(handle: *mut sys::BNBinaryView) -> Option<Self> {
+        if handle.is_null() {
+            None
+        } else {
+            Some(Self { handle })
+        }
+    }
+
+    pub(crate) fn handle(&self) -> *mut sys::BNBinaryView {
+        self.handle
+    }
+
+    /// Path of the file the view was loaded from, also for views saved to a database
+    pub(crate) fn original_filename(&self) -> Option<String> {
+        unsafe {
+            let file = sys::BNGetFileForView(self.handle);
+            if file.is_null() {
+                return None;
+            }
+            let path = core_string(sys::BNGetOriginalFilename(file));
+            sys::BNFreeFileMetadata(file);
+            path
+        }
+    }
+
+    pub(crate) fn image_base(&self) -> u64 {
+        unsafe { sys::BNGetImageBase(self.handle) }
+    }
+}
+
+/// Owned analysis function reference
+pub(crate) struct CoreFunction {
+    handle: *mut sys::BNFunction,
+}
+
+impl Drop for CoreFunction {
+    fn drop(&mut self) {
+        unsafe { sys::BNFreeFunction(self.handle) }
+    }
+}
+
+impl CoreFunction {
+    /// Wrap a borrowed function reference, taking a new reference to it
+    pub(crate) unsafe fn from_borrowed(handle: *mut sys::BNFunction) -> Option<Self> {
+        if handle.is_null() {
+            None
+        } else {
+            Some(Self {
+                handle: sys::BNNewFunctionReference(handle),
+            })
+        }
+    }
+
+    /// Function starting at `address`, else the first one containing it
+    pub(crate) fn at(view: &CoreView, address: u64) -> Option<Self> {
+        Self::starting_at(view, address).or_else(|| Self::containing(view, address))
+    }
+
+    pub(crate) fn starting_at(view: &CoreView, address: u64) -> Option<Self> {
+        unsafe { Self::first_of(sys::BNGetAnalysisFunctionsForAddress, view, address) }
+    }
+
+    pub(crate) fn containing(view: &CoreView, address: u64) -> Option<Self> {
+        unsafe { Self::first_of(sys::BNGetAnalysisFunctionsContainingAddress, view, address) }
+    }
+
+    unsafe fn first_of(
+        list: unsafe extern "C" fn(
+            *mut sys::BNBinaryView,
+            u64,
+            *mut usize,
+        ) -> *mut *mut sys::BNFunction,
+        view: &CoreView,
+        address: u64,
+    ) -> Option<Self> {
+        let mut count = 0usize;
+        let funcs = list(view.handle(), address, &mut count);
+        if funcs.is_null() {
+            return None;
+        }
+        let func = std::slice::from_raw_parts(funcs, count)
+            .first()
+            .and_then(|&f| Self::from_borrowed(f));
+        sys::BNFreeFunctionList(funcs, count);
+        func
+    }
+
+    pub(crate) fn handle(&self) -> *mut sys::BNFunction {
+        self.handle
+    }
+
+    pub(crate) fn start(&self) -> u64 {
+        unsafe { sys::BNGetFunctionStart(self.handle) }
+    }
+
+    pub(crate) fn arch(&self) -> *mut sys::BNArchitecture {
+        unsafe { sys::BNGetFunctionArchitecture(self.handle) }
+    }
+
+    /// Symbol name, or `sub_<addr>` for unnamed functions
+    pub(crate) fn name(&self) -> String {
+        self.symbol_name(sys::BNGetSymbolFullName)
+            .unwrap_or_else(|| format!("sub_{:x}", self.start()))
+    }
+
+    /// Demangled name without parameters, or `sub_<addr>` for unnamed functions
+    pub(crate) fn short_name(&self) -> String {
+        self.symbol_name(sys::BNGetSymbolShortName)
+            .unwrap_or_else(|| format!("sub_{:x}", self.start()))
+    }
+
+    fn symbol_name(
+        &self,
+        get: unsafe extern "C" fn(*mut sys::BNSymbol) -> *mut c_char,
+    ) -> Option<String> {
+        unsafe {
+            let sym = sys::BNGetFunctionSymbol(self.handle);
+            if sym.is_null() {
+                return None;
+            }
+            let name = core_string(get(sym));
+            sys::BNFreeSymbol(sym);
+            name
+        }
+    }
+
+    pub(crate) fn return_type(&self) -> Option<CoreType> {
+        unsafe { CoreType::from_raw(sys::BNGetFunctionReturnType(self.handle).type_) }
+    }
+
+    pub(crate) fn calling_convention(&self) -> Option<CallingConvention> {
+        unsafe {
+            CallingConvention::from_raw(sys::BNGetFunctionCallingConvention(self.handle).convention)
+        }
+    }
+
+    pub(crate) fn parameter_variables(&self) -> Vec<sys::BNVariable> {
+        unsafe {
+            let mut params = sys::BNGetFunctionParameterVariables(self.handle);
+            let vars = if params.vars.is_null() {
+                Vec::new()
+            } else {
+                std::slice::from_raw_parts(params.vars, params.count).to_vec()
+            };
+            sys::BNFreeParameterVariables(&mut params);
+            vars
+        }
+    }
+
+    pub(crate) fn variable_name(&self, var: &sys::BNVariable) -> String {
+        unsafe { core_string(sys::BNGetVariableNameOrDefault(self.handle, var)) }
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn variable_type(&self, var: &sys::BNVariable) -> Option<CoreType> {
+        unsafe { CoreType::from_raw(sys::BNGetVariableType(self.handle, var).type_) }
+    }
+
+    /// Every variable of the function with its name and type
+    pub(crate) fn variables(&self) -> Vec<(sys::BNVariable, String, Option<CoreType>)> {
+        unsafe {
+            let mut count = 0usize;
+            let vars = sys::BNGetFunctionVariables(self.handle, &mut count);
+            if vars.is_null() {
+                return Vec::new();
+            }
+            let result = std::slice::from_raw_parts(vars, count)
+                .iter()
+                .map(|v| {
+                    let name = if v.name.is_null() {
+                        String::new()
+                    } else {
+                        CStr::from_ptr(v.name).to_string_lossy().into_owned()
+                    };
+                    (v.var, name, CoreType::from_borrowed(v.type_))
+                })
+                .collect();
+            sys::BNFreeVariableNameAndTypeList(vars, count);
+            result
+        }
+    }
+
+    /// Offset of the stack pointer at `address` from its value at the function entry
+    pub(crate) fn stack_offset_at(&self, address: u64) -> Option<i64> {
+        unsafe {
+            let arch = self.arch();
+            let sp = sys::BNGetArchitectureStackPointerRegister(arch);
+            let value = sys::BNGetRegisterValueAtInstruction(self.handle, arch, address, sp);
+            (value.state == BNRegisterValueType::StackFrameOffset).then_some(value.value)
+        }
+    }
+
+    /// HLIL text of the statement containing the instruction at `address`
+    pub(crate) fn hlil_line(&self, address: u64) -> Option<String> {
+        unsafe {
+            let mlil = sys::BNGetFunctionMediumLevelIL(self.handle);
+            if mlil.is_null() {
+                return None;
+            }
+            let hlil = sys::BNGetFunctionHighLevelIL(self.handle);
+            if hlil.is_null() {
+                sys::BNFreeMediumLevelILFunction(mlil);
+                return None;
+            }
+            let line = Self::hlil_text(mlil, hlil, self.arch(), address);
+            sys::BNFreeHighLevelILFunction(hlil);
+            sys::BNFreeMediumLevelILFunction(mlil);
+            line
+        }
+    }
+
+    unsafe fn hlil_text(
+        mlil: *mut sys::BNMediumLevelILFunction,
+        hlil: *mut sys::BNHighLevelILFunction,
+        arch: *mut sys::BNArchitecture,
+        address: u64,
+    ) -> Option<String> {
+        // lookups past the end mean the address has no IL instruction
+        let mlil_instr = sys::BNMediumLevelILGetInstructionStart(mlil, arch, address);
+        if mlil_instr >= sys::BNGetMediumLevelILInstructionCount(mlil) {
+            return None;
+        }
+        let hlil_instr = sys::BNGetHighLevelILInstructionIndex(mlil, mlil_instr);
+        if hlil_instr >= sys::BNGetHighLevelILInstructionCount(hlil) {
+            return None;
+        }
+        let expr = sys::BNGetHighLevelILIndexForInstruction(hlil, hlil_instr);
+
+        let mut count = 0usize;
+        let lines =
+            sys::BNGetHighLevelILExprText(hlil, expr, false, &mut count, std::ptr::null_mut());
+        if lines.is_null() {
+            return None;
+        }
+        let text = std::slice::from_raw_parts(lines, count)
+            .iter()
+            .map(|line| {
+                std::slice::from_raw_parts(line.tokens, line.count)
+                    .iter()
+                    .filter(|t| !t.text.is_null())
+                    .map(|t| CStr::from_ptr(t.text).to_string_lossy())
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        sys::BNFreeDisassemblyTextLines(lines, count);
+        Some(text)
+    }
+}
@@ -0,0 +1,1037 @@
+// adapter independent stack unwinding from .eh_frame / .debug_frame call frame information
+
+use crate::elf::{link_base, ElfHeader, PT_GNU_EH_FRAME, PT_LOAD};
+use crate::symbols::SymbolLocation;
+use crate::types::{CoreFunction, CoreView};
+use crate::{DebugModule, DebuggerController};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+const MAX_FRAMES: usize = 256;
+/// Largest .eh_frame read from target memory
+const MAX_EH_FRAME_SIZE: u64 = 0x100_0000;
+
+const DW_EH_PE_OMIT: u8 = 0xff;
+const DW_EH_PE_PCREL: u8 = 0x10;
+
+/// DWARF register numbering of an architecture
+struct DwarfRegisters {
+    /// Register names indexed by DWARF number
+    names: &'static [&'static str],
+    sp: u16,
+    fp: u16,
+    /// Return address column used when there is no CFI
+    return_address: u16,
+    /// The return address is passed in a register instead of on the stack
+    link_register: bool,
+}
+
+const X86_64_REGISTERS: DwarfRegisters = DwarfRegisters {
+    names: &[
+        "rax", "rdx", "rcx", "rbx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12",
+        "r13", "r14", "r15", "rip",
+    ],
+    sp: 7,
+    fp: 6,
+    return_address: 16,
+    link_register: false,
+};
+
+const X86_REGISTERS: DwarfRegisters = DwarfRegisters {
+    names: &[
+        "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "eip",
+    ],
+    sp: 4,
+    fp: 5,
+    return_address: 8,
+    link_register: false,
+};
+
+const AARCH64_REGISTERS: DwarfRegisters = DwarfRegisters {
+    names: &[
+        "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13",
+        "x14", "x15", "x16", "x17", "x18", "x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26",
+        "x27", "x28", "x29", "x30", "sp",
+    ],
+    sp: 31,
+    fp: 29,
+    return_address: 30,
+    link_register: true,
+};
+
+fn dwarf_registers(arch: &str) -> Option<&'static DwarfRegisters> {
+    match arch {
+        "x86_64" => Some(&X86_64_REGISTERS),
+        "x86" => Some(&X86_REGISTERS),
+        "aarch64" => Some(&AARCH64_REGISTERS),
+        _ => None,
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+
+    fn uleb(&mut self) -> Option<u64> {
+        let mut result = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                result |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Some(result);
+            }
+        }
+    }
+
+    fn sleb(&mut self) -> Option<i64> {
+        let mut result = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                result |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    result |= -1i64 << shift;
+                }
+                return Some(result);
+            }
+        }
+    }
+
+    fn cstr(&mut self) -> Option<&'a [u8]> {
+        let len = self.data.get(self.pos..)?.iter().position(|&b| b == 0)?;
+        let s = self.bytes(len)?;
+        self.pos += 1;
+        Some(s)
+    }
+
+    fn address(&mut self, size: u8) -> Option<u64> {
+        match size {
+            4 => self.u32().map(u64::from),
+            8 => self.u64(),
+            _ => None,
+        }
+    }
+
+    /// Read a `DW_EH_PE_*` encoded pointer of a section loaded at `base`
+    fn encoded(&mut self, encoding: u8, base: u64, address_size: u8) -> Option<u64> {
+        let at = base.wrapping_add(self.pos as u64);
+        let value = match encoding & 0x0f {
+            0x00 => self.address(address_size)?,
+            0x01 => self.uleb()?,
+            0x02 => self.u16()? as u64,
+            0x03 => self.u32()? as u64,
+            0x04 | 0x0c => self.u64()?,
+            0x09 => self.sleb()? as u64,
+            0x0a => self.u16()? as i16 as i64 as u64,
+            0x0b => self.u32()? as i32 as i64 as u64,
+            _ => return None,
+        };
+        // the indirect bit only appears on personality pointers, which are skipped
+        match encoding & 0x70 {
+            0x00 => Some(value),
+            DW_EH_PE_PCREL => Some(at.wrapping_add(value)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SectionKind {
+    EhFrame,
+    DebugFrame,
+}
+
+struct CfiSection {
+    kind: SectionKind,
+    /// Address the section data was read from, link-time for files, runtime for memory
+    address: u64,
+    data: Vec<u8>,
+}
+
+/// Length and id fields shared by CIEs and FDEs
+struct EntryHeader {
+    /// Offset right after the id field
+    body: usize,
+    end: usize,
+    /// Section offset of the CIE, None for CIEs
+    cie: Option<usize>,
+}
+
+impl EntryHeader {
+    fn parse(section: &CfiSection, offset: usize) -> Option<Self> {
+        let mut r = Reader::new(&section.data, offset);
+        let (length, is_64) = match r.u32()? {
+            // zero terminator of .eh_frame
+            0 => return None,
+            0xffff_ffff => (r.u64()?, true),
+            length => (length as u64, false),
+        };
+        let end = r.pos.checked_add(length as usize)?;
+        if end > section.data.len() {
+            return None;
+        }
+        let id_pos = r.pos;
+        let id = if is_64 { r.u64()? } else { r.u32()? as u64 };
+        let cie = match section.kind {
+            SectionKind::EhFrame if id == 0 => None,
+            // relative to the id field
+            SectionKind::EhFrame => Some((id_pos as u64).checked_sub(id)? as usize),
+            SectionKind::DebugFrame if id == 0xffff_ffff || id == u64::MAX => None,
+            SectionKind::DebugFrame => Some(id as usize),
+        };
+        Some(Self {
+            body: r.pos,
+            end,
+            cie,
+        })
+    }
+}
+
+struct Cie {
+    code_align: u64,
+    data_align: i64,
+    return_address: u16,
+    address_size: u8,
+    fde_encoding: u8,
+    augmented: bool,
+    signal_frame: bool,
+    section: usize,
+    instructions: Range<usize>,
+}
+
+impl Cie {
+    fn parse(section: &CfiSection, index: usize, offset: usize, address_size: u8) -> Option<Self> {
+        let header = EntryHeader::parse(section, offset)?;
+        if header.cie.is_some() {
+            return None;
+        }
+        let mut r = Reader::new(&section.data[..header.end], header.body);
+        let version = r.u8()?;
+        if !matches!(version, 1 | 3 | 4) {
+            return None;
+        }
+        let augmentation = r.cstr()?;
+        let mut address_size = address_size;
+        if augmentation.windows(2).any(|w| w == b"eh") {
+            r.bytes(address_size as usize)?;
+        }
+        if version >= 4 {
+            address_size = r.u8()?;
+            let _segment_size = r.u8()?;
+        }
+        let code_align = r.uleb()?;
+        let data_align = r.sleb()?;
+        let return_address = if version == 1 {
+            r.u8()? as u16
+        } else {
+            r.uleb()? as u16
+        };
+
+        let mut fde_encoding = 0;
+        let mut signal_frame = false;
+        let augmented = augmentation.first() == Some(&b'z');
+        if augmented {
+            let len = r.uleb()? as usize;
+            let end = r.pos.checked_add(len)?;
+            for &c in &augmentation[1..] {
+                match c {
+                    b'L' => {
+                        r.u8()?;
+                    }
+                    b'P' => {
+                        let encoding = r.u8()?;
+                        r.encoded(encoding & 0x7f, section.address, address_size)?;
+                    }
+                    b'R' => fde_encoding = r.u8()?,
+                    b'S' => signal_frame = true,
+                    b'B' | b'G' => {}
+                    _ => break,
+                }
+            }
+            r.pos = end;
+        } else if !augmentation.is_empty() && augmentation != b"eh" {
+            // unknown augmentation data without a length cannot be skipped
+            return None;
+        }
+
+        Some(Self {
+            code_align,
+            data_align,
+            return_address,
+            address_size,
+            fde_encoding,
+            augmented,
+            signal_frame,
+            section: index,
+            instructions: r.pos..header.end,
+        })
+    }
+}
+
+struct Fde {
+    start: u64,
+    end: u64,
+    cie: usize,
+    instructions: Range<usize>,
+}
+
+impl Fde {
+    fn parse(
+        section: &CfiSection,
+        header: &EntryHeader,
+        cie: &Cie,
+        cie_index: usize,
+    ) -> Option<Self> {
+        let mut r = Reader::new(&section.data[..header.end], header.body);
+        let start = r.encoded(cie.fde_encoding, section.address, cie.address_size)?;
+        let length = r.encoded(cie.fde_encoding & 0x0f, section.address, cie.address_size)?;
+        if cie.augmented {
+            let len = r.uleb()? as usize;
+            r.bytes(len)?;
+        }
+        // entries of functions discarded at link time
+        if start == 0 || length == 0 {
+            return None;
+        }
+        Some(Self {
+            start,
+            end: start.checked_add(length)?,
+            cie: cie_index,
+            instructions: r.pos..header.end,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RegisterRule {
+    Undefined,
+    SameValue,
+    /// Saved at CFA + offset
+    Offset(i64),
+    /// The value is CFA + offset
+    ValOffset(i64),
+    Register(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CfaRule {
+    RegisterOffset(u16, i64),
+    /// Not defined yet or a DWARF expression, which is not evaluated
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+struct UnwindRow {
+    cfa: CfaRule,
+    registers: HashMap<u16, RegisterRule>,
+}
+
+impl UnwindRow {
+    /// Run a CFA program until the location passes `target`
+    fn execute(
+        &mut self,
+        cie: &Cie,
+        section: &CfiSection,
+        program: Range<usize>,
+        initial: Option<&UnwindRow>,
+        start: u64,
+        target: u64,
+    ) -> Option<()> {
+        let mut r = Reader::new(section.data.get(..program.end)?, program.start);
+        let mut location = start;
+        let mut stack: Vec<UnwindRow> = Vec::new();
+        while !r.at_end() {
+            let op = r.u8()?;
+            let operand = (op & 0x3f) as u16;
+            let mut advance_to = None;
+            match op & 0xc0 {
+                0x40 => advance_to = Some(location + operand as u64 * cie.code_align),
+                0x80 => {
+                    let offset = r.uleb()? as i64 * cie.data_align;
+                    self.registers.insert(operand, RegisterRule::Offset(offset));
+                }
+                0xc0 => self.restore(operand, initial),
+                _ => match op {
+                    // DW_CFA_nop
+                    0x00 => {}
+                    // DW_CFA_set_loc
+                    0x01 => {
+                        advance_to =
+                            Some(r.encoded(cie.fde_encoding, section.address, cie.address_size)?)
+                    }
+                    // DW_CFA_advance_loc1/2/4
+                    0x02 => advance_to = Some(location + r.u8()? as u64 * cie.code_align),
+                    0x03 => advance_to = Some(location + r.u16()? as u64 * cie.code_align),
+                    0x04 => advance_to = Some(location + r.u32()? as u64 * cie.code_align),
+                    // DW_CFA_offset_extended
+                    0x05 => {
+                        let reg = r.uleb()? as u16;
+                        let offset = r.uleb()? as i64 * cie.data_align;
+                        self.registers.insert(reg, RegisterRule::Offset(offset));
+                    }
+                    // DW_CFA_restore_extended
+                    0x06 => {
+                        let reg = r.uleb()? as u16;
+                        self.restore(reg, initial);
+                    }
+                    // DW_CFA_undefined
+                    0x07 => {
+                        self.registers
+                            .insert(r.uleb()? as u16, RegisterRule::Undefined);
+                    }
+                    // DW_CFA_same_value
+                    0x08 => {
+                        self.registers
+                            .insert(r.uleb()? as u16, RegisterRule::SameValue);
+                    }
+                    // DW_CFA_register
+                    0x09 => {
+                        let reg = r.uleb()? as u16;
+                        let other = r.uleb()? as u16;
+                        self.registers.insert(reg, RegisterRule::Register(other));
+                    }
+                    // DW_CFA_remember_state
+                    0x0a => stack.push(self.clone()),
+                    // DW_CFA_restore_state
+                    0x0b => *self = stack.pop()?,
+                    // DW_CFA_def_cfa
+                    0x0c => {
+                        let reg = r.uleb()? as u16;
+                        let offset = r.uleb()? as i64;
+                        self.cfa = CfaRule::RegisterOffset(reg, offset);
+                    }
+                    // DW_CFA_def_cfa_register
+                    0x0d => {
+                        let reg = r.uleb()? as u16;
+                        if let CfaRule::RegisterOffset(_, offset) = self.cfa {
+                            self.cfa = CfaRule::RegisterOffset(reg, offset);
+                        } else {
+                            self.cfa = CfaRule::RegisterOffset(reg, 0);
+                        }
+                    }
+                    // DW_CFA_def_cfa_offset
+                    0x0e => {
+                        let offset = r.uleb()? as i64;
+                        self.set_cfa_offset(offset);
+                    }
+                    // DW_CFA_def_cfa_expression
+                    0x0f => {
+                        let len = r.uleb()? as usize;
+                        r.bytes(len)?;
+                        self.cfa = CfaRule::Unknown;
+                    }
+                    // DW_CFA_expression, DW_CFA_val_expression
+                    0x10 | 0x16 => {
+                        let reg = r.uleb()? as u16;
+                        let len = r.uleb()? as usize;
+                        r.bytes(len)?;
+                        self.registers.insert(reg, RegisterRule::Undefined);
+                    }
+                    // DW_CFA_offset_extended_sf
+                    0x11 => {
+                        let reg = r.uleb()? as u16;
+                        let offset = r.sleb()? * cie.data_align;
+                        self.registers.insert(reg, RegisterRule::Offset(offset));
+                    }
+                    // DW_CFA_def_cfa_sf
+                    0x12 => {
+                        let reg = r.uleb()? as u16;
+                        let offset = r.sleb()? * cie.data_align;
+                        self.cfa = CfaRule::RegisterOffset(reg, offset);
+                    }
+                    // DW_CFA_def_cfa_offset_sf
+                    0x13 => {
+                        let offset = r.sleb()? * cie.data_align;
+                        self.set_cfa_offset(offset);
+                    }
+                    // DW_CFA_val_offset
+                    0x14 => {
+                        let reg = r.uleb()? as u16;
+                        let offset = r.uleb()? as i64 * cie.data_align;
+                        self.registers.insert(reg, RegisterRule::ValOffset(offset));
+                    }
+                    // DW_CFA_val_offset_sf
+                    0x15 => {
+                        let reg = r.uleb()? as u16;
+                        let offset = r.sleb()? * cie.data_align;
+                        self.registers.insert(reg, RegisterRule::ValOffset(offset));
+                    }
+                    // DW_CFA_GNU_window_save / DW_CFA_AARCH64_negate_ra_state
+                    0x2d => {}
+                    // DW_CFA_GNU_args_size
+                    0x2e => {
+                        r.uleb()?;
+                    }
+                    // DW_CFA_GNU_negative_offset_extended
+                    0x2f => {
+                        let reg = r.uleb()? as u16;
+                        let offset = -(r.uleb()? as i64) * cie.data_align;
+                        self.registers.insert(reg, RegisterRule::Offset(offset));
+                    }
+                    _ => return None,
+                },
+            }
+            if let Some(next) = advance_to {
+                if next > target {
+                    break;
+                }
+                location = next;
+            }
+        }
+        Some(())
+    }
+
+    fn restore(&mut self, reg: u16, initial: Option<&UnwindRow>) {
+        match initial.and_then(|row| row.registers.get(&reg)) {
+            Some(&rule) => self.registers.insert(reg, rule),
+            None => self.registers.remove(&reg),
+        };
+    }
+
+    fn set_cfa_offset(&mut self, offset: i64) {
+        if let CfaRule::RegisterOffset(reg, _) = self.cfa {
+            self.cfa = CfaRule::RegisterOffset(reg, offset);
+        }
+    }
+}
+
+/// Parsed CFI of one module
+pub(crate) struct CfiTable {
+    /// Runtime address minus the addresses used in the sections
+    bias: u64,
+    sections: Vec<CfiSection>,
+    cies: Vec<Cie>,
+    /// Sorted by start address
+    fdes: Vec<Fde>,
+}
+
+impl CfiTable {
+    fn parse(sections: Vec<CfiSection>, bias: u64, address_size: u8) -> Self {
+        let mut cies = Vec::new();
+        let mut fdes = Vec::new();
+        for (index, section) in sections.iter().enumerate() {
+            let mut cie_offsets: HashMap<usize, usize> = HashMap::new();
+            let mut offset = 0;
+            while let Some(header) = EntryHeader::parse(section, offset) {
+                offset = header.end;
+                let Some(cie_offset) = header.cie else {
+                    continue;
+                };
+                let cie = match cie_offsets.get(&cie_offset) {
+                    Some(&cie) => cie,
+                    None => {
+                        let Some(cie) = Cie::parse(section, index, cie_offset, address_size) else {
+                            continue;
+                        };
+                        cies.push(cie);
+                        cie_offsets.insert(cie_offset, cies.len() - 1);
+                        cies.len() - 1
+                    }
+                };
+                if let Some(fde) = Fde::parse(section, &header, &cies[cie], cie) {
+                    fdes.push(fde);
+                }
+            }
+        }
+        fdes.sort_by_key(|fde| fde.start);
+        Self {
+            bias,
+            sections,
+            cies,
+            fdes,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.fdes.is_empty()
+    }
+
+    fn find(&self, address: u64) -> Option<&Fde> {
+        let i = self.fdes.partition_point(|fde| fde.start <= address);
+        let fde = self.fdes.get(i.checked_sub(1)?)?;
+        (address < fde.end).then_some(fde)
+    }
+
+    /// Unwind rules in effect at the runtime address `pc`
+    fn row(&self, pc: u64) -> Option<(UnwindRow, &Cie)> {
+        let address = pc.wrapping_sub(self.bias);
+        let fde = self.find(address)?;
+        let cie = &self.cies[fde.cie];
+        let section = &self.sections[cie.section];
+        let mut row = UnwindRow {
+            cfa: CfaRule::Unknown,
+            registers: HashMap::new(),
+        };
+        row.execute(cie, section, cie.instructions.clone(), None, 0, u64::MAX)?;
+        let initial = row.clone();
+        row.execute(
+            cie,
+            section,
+            fde.instructions.clone(),
+            Some(&initial),
+            fde.start,
+            address,
+        )?;
+        Some((row, cie))
+    }
+}
+
+/// Module path and base
+type ModuleKey = (String, u64);
+
+/// Parsed CFI of a module, None if it has none
+type CachedTable = Option<Arc<CfiTable>>;
+
+/// Parsed CFI tables by module
+#[derive(Default)]
+pub(crate) struct UnwindCache {
+    tables: Mutex<HashMap<ModuleKey, CachedTable>>,
+}
+
+/// .eh_frame and .debug_frame of the module's file on disk
+fn cfi_from_file(module: &DebugModule, address_size: u8) -> Option<CfiTable> {
+    let file = std::fs::read(&module.name).ok()?;
+    let header = ElfHeader::parse(&file)?;
+    let headers = header.program_headers(file.get(header.phoff as usize..)?);
+    let bias = module.address.wrapping_sub(link_base(&headers)?);
+    let sections: Vec<CfiSection> = header
+        .sections(&file)
+        .into_iter()
+        .filter_map(|s| {
+            let kind = match s.name.as_str() {
+                ".eh_frame" => SectionKind::EhFrame,
+                ".debug_frame" => SectionKind::DebugFrame,
+                _ => return None,
+            };
+            Some(CfiSection {
+                kind,
+                address: s.address,
+                data: s.data.to_vec(),
+            })
+        })
+        .collect();
+    let table = CfiTable::parse(sections, bias, address_size);
+    (!table.is_empty()).then_some(table)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnwindMethod {
+    /// Registers of the stopped thread
+    Context,
+    /// Call frame information from .eh_frame or .debug_frame
+    Cfi,
+    /// Binary Ninja's stack pointer analysis of the function
+    StackAnalysis,
+}
+
+impl fmt::Display for UnwindMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Context => "context",
+            Self::Cfi => "cfi",
+            Self::StackAnalysis => "stack analysis",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UnwoundFrame {
+    pub index: usize,
+    pub pc: u64,
+    pub sp: u64,
+    /// None once the frame pointer could not be recovered
+    pub fp: Option<u64>,
+    pub function_name: Option<String>,
+    pub function_start: Option<u64>,
+    pub module: Option<String>,
+    /// How this frame was recovered from the one below it
+    pub method: UnwindMethod,
+}
+
+impl fmt::Display for UnwoundFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} 0x{:x}", self.index, self.pc)?;
+        match (&self.function_name, self.function_start) {
+            (Some(name), Some(start)) if start != self.pc => {
+                write!(f, " in {}+0x{:x}", name, self.pc.wrapping_sub(start))?
+            }
+            (Some(name), _) => write!(f, " in {}", name)?,
+            _ => {}
+        }
+        if let Some(module) = &self.module {
+            write!(f, " ({})", module)?;
+        }
+        write!(f, " [{}]", self.method)
+    }
+}
+
+/// Register values of a frame by DWARF number, and its pc
+struct FrameState {
+    registers: HashMap<u16, u64>,
+    pc: u64,
+}
+
+enum Step {
+    Caller(FrameState, UnwindMethod, bool),
+    /// The return address is explicitly undefined, e.g. in `_start`
+    End,
+}
+
+impl DebuggerController {
+    /// Unwind the active thread's stack without relying on the adapter.
+    ///
+    /// Each step uses the CFI of the module containing the pc, read from the
+    /// file on disk or, failing that, from the `.eh_frame` mapped in memory.
+    /// Functions without CFI are stepped over with Binary Ninja's stack pointer
+    /// analysis, which needs the live view or a `module_view` of the module. Supports
+    /// x86_64, x86 and aarch64.
+    pub fn unwind(&self) -> Vec<UnwoundFrame> {
+        let Some(regs) = self.arch_name().as_deref().and_then(dwarf_registers) else {
+            log::warn!("no DWARF register mapping for this architecture");
+            return Vec::new();
+        };
+        let registers = self.registers();
+        let mut state = FrameState {
+            registers: regs
+                .names
+                .iter()
+                .enumerate()
+                .filter_map(|(number, name)| {
+                    let reg = registers.iter().find(|r| r.name == *name)?;
+                    Some((number as u16, reg.value_u64()))
+                })
+                .collect(),
+            pc: self.ip(),
+        };
+        let modules = self.modules();
+
+        let mut frames: Vec<UnwoundFrame> = Vec::new();
+        let mut method = UnwindMethod::Context;
+        // the pc of the innermost and of signal frames is not a return address
+        let mut exact_pc = true;
+        while frames.len() < MAX_FRAMES {
+            let Some(&sp) = state.registers.get(&regs.sp) else {
+                break;
+            };
+            let lookup = if exact_pc {
+                state.pc
+            } else {
+                state.pc.wrapping_sub(1)
+            };
+            let view = self.analysis_view(lookup);
+            let function = view
+                .as_ref()
+                .and_then(|view| CoreFunction::at(view, lookup));
+            let module = modules.iter().find(|m| contains(m, lookup));
+            let (function_name, function_start) = match &function {
+                Some(f) => (Some(f.name()), Some(f.start())),
+                None => match self.symbolize(lookup) {
+                    Some(SymbolLocation {
+                        symbol: Some(symbol),
+                        offset,
+                        ..
+                    }) => (Some(symbol), Some(lookup - offset)),
+                    _ => (None, None),
+                },
+            };
+            frames.push(UnwoundFrame {
+                index: frames.len(),
+                pc: state.pc,
+                sp,
+                fp: state.registers.get(&regs.fp).copied(),
+                function_name,
+                function_start,
+                module: module.map(|m| m.short_name.clone()),
+                method,
+            });
+
+            let step = module
+                .and_then(|module| self.cfi_table(module))
+                .and_then(|table| self.step_cfi(&table, regs, &state, lookup, sp))
+                .or_else(|| {
+                    self.step_stack_analysis(
+                        view.as_ref()?,
+                        regs,
+                        &state,
+                        lookup,
+                        frames.len() == 1,
+                    )
+                });
+            let Some(Step::Caller(caller, next_method, signal_frame)) = step else {
+                break;
+            };
+            let Some(&caller_sp) = caller.registers.get(&regs.sp) else {
+                break;
+            };
+            // the stack grows down, a caller never has a lower stack pointer
+            if caller.pc == 0 || caller_sp < sp || (caller_sp == sp && frames.len() > 1) {
+                break;
+            }
+            state = caller;
+            method = next_method;
+            exact_pc = signal_frame;
+        }
+        frames
+    }
+
+    /// Unwind the stack of another thread, see `unwind`
+    pub fn unwind_thread(&self, tid: u32) -> Vec<UnwoundFrame> {
+        self.with_thread(tid, |dbg| dbg.unwind())
+            .unwrap_or_default()
+    }
+
+    fn cfi_table(&self, module: &DebugModule) -> Option<Arc<CfiTable>> {
+        let key = (module.name.clone(), module.address);
+        if let Some(table) = self.inner.unwind.tables.lock().unwrap().get(&key) {
+            return table.clone();
+        }
+        let address_size = self.address_size() as u8;
+        let table = cfi_from_file(module, address_size)
+            .or_else(|| self.cfi_from_memory(module, address_size))
+            .map(Arc::new);
+        if table.is_none() {
+            log::debug!("no call frame information for {}", module.short_name);
+        }
+        self.inner
+            .unwind
+            .tables
+            .lock()
+            .unwrap()
+            .insert(key, table.clone());
+        table
+    }
+
+    /// .eh_frame located through PT_GNU_EH_FRAME of the module mapped in memory
+    fn cfi_from_memory(&self, module: &DebugModule, address_size: u8) -> Option<CfiTable> {
+        let (_, headers) = self.elf_headers(module.address)?;
+        let bias = module.address.wrapping_sub(link_base(&headers)?);
+
+        // .eh_frame_hdr: version, eh_frame_ptr encoding, fde_count encoding,
+        // table encoding, then eh_frame_ptr
+        let hdr = headers.iter().find(|ph| ph.p_type == PT_GNU_EH_FRAME)?;
+        let hdr_address = hdr.vaddr.wrapping_add(bias);
+        let hdr_data = self.read_memory(hdr_address, 4 + address_size as usize)?;
+        let mut r = Reader::new(&hdr_data, 1);
+        let encoding = r.u8()?;
+        if encoding == DW_EH_PE_OMIT {
+            return None;
+        }
+        r.pos = 4;
+        let eh_frame = r.encoded(encoding, hdr_address, address_size)?;
+
+        // the section size is not recorded, read up to the end of its segment
+        let segment = headers
+            .iter()
+            .find(|ph| ph.p_type == PT_LOAD && ph.contains(eh_frame.wrapping_sub(bias)))?;
+        let end = (segment.vaddr + segment.memsz).wrapping_add(bias);
+        let size = end.checked_sub(eh_frame)?.min(MAX_EH_FRAME_SIZE);
+        let section = CfiSection {
+            kind: SectionKind::EhFrame,
+            address: eh_frame,
+            data: self.read_memory(eh_frame, size as usize)?,
+        };
+        let table = CfiTable::parse(vec![section], 0, address_size);
+        (!table.is_empty()).then_some(table)
+    }
+
+    fn step_cfi(
+        &self,
+        table: &CfiTable,
+        regs: &DwarfRegisters,
+        state: &FrameState,
+        lookup: u64,
+        sp: u64,
+    ) -> Option<Step> {
+        let (row, cie) = table.row(lookup)?;
+        let CfaRule::RegisterOffset(reg, offset) = row.cfa else {
+            return None;
+        };
+        let cfa = state.registers.get(&reg)?.wrapping_add(offset as u64);
+        // a bogus row for this pc rather than a real frame
+        if cfa < sp {
+            return None;
+        }
+
+        let mut registers = state.registers.clone();
+        for (&reg, &rule) in &row.registers {
+            match rule {
+                RegisterRule::Undefined => {
+                    registers.remove(&reg);
+                }
+                RegisterRule::SameValue => {}
+                RegisterRule::Offset(offset) => {
+                    registers.insert(reg, self.read_pointer(cfa.wrapping_add(offset as u64))?);
+                }
+                RegisterRule::ValOffset(offset) => {
+                    registers.insert(reg, cfa.wrapping_add(offset as u64));
+                }
+                RegisterRule::Register(other) => match state.registers.get(&other) {
+                    Some(&value) => {
+                        registers.insert(reg, value);
+                    }
+                    None => {
+                        registers.remove(&reg);
+                    }
+                },
+            }
+        }
+        let Some(&pc) = registers.get(&cie.return_address) else {
+            return Some(Step::End);
+        };
+        registers.insert(regs.sp, cfa);
+        Some(Step::Caller(
+            FrameState { registers, pc },
+            UnwindMethod::Cfi,
+            cie.signal_frame,
+        ))
+    }
+
+    fn step_stack_analysis(
+        &self,
+        view: &CoreView,
+        regs: &DwarfRegisters,
+        state: &FrameState,
+        lookup: u64,
+        innermost: bool,
+    ) -> Option<Step> {
+        let func = CoreFunction::at(view, lookup)?;
+        // the offset after the call returned, which is this frame's stack pointer
+        let offset = func.stack_offset_at(state.pc)?;
+        let entry_sp = state.registers.get(&regs.sp)?.wrapping_sub(offset as u64);
+
+        let (pc, caller_sp) = if regs.link_register {
+            // the prologue may have saved and reused the link register, it is
+            // only trustworthy in the innermost frame
+            if !innermost {
+                return None;
+            }
+            (*state.registers.get(&regs.return_address)?, entry_sp)
+        } else {
+            (
+                self.read_pointer(entry_sp)?,
+                entry_sp + self.address_size() as u64,
+            )
+        };
+        let mut registers = state.registers.clone();
+        registers.insert(regs.sp, caller_sp);
+        registers.insert(regs.return_address, pc);
+        Some(Step::Caller(
+            FrameState { registers, pc },
+            UnwindMethod::StackAnalysis,
+            false,
+        ))
+    }
+}
+
+fn contains(module: &DebugModule, address: u64) -> bool {
+    address >= module.address && address - module.address < module.size as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // gcc style x86_64 .eh_frame: `push rbp; mov rbp, rsp; ...` at 0x1000
+    fn eh_frame() -> CfiSection {
+        let mut data = vec![
+            // CIE: length, id, version, "zR", code align 1, data align -8, ra 16
+            0x14, 0, 0, 0, 0, 0, 0, 0, 1, b'z', b'R', 0, 1, 0x78, 16,
+            // augmentation length, pcrel|sdata4
+            1, 0x1b, // def_cfa rsp+8, offset rip at cfa-8
+            0x0c, 7, 8, 0x90, 1, 0, 0,
+        ];
+        let fde = data.len();
+        data.extend_from_slice(&[0x1c, 0, 0, 0]);
+        data.extend_from_slice(&((fde + 4) as u32).to_le_bytes());
+        // pc_begin relative to its own position, pc_range
+        let pc_begin = (0x1000 - (fde as i64 + 8)) as i32;
+        data.extend_from_slice(&pc_begin.to_le_bytes());
+        data.extend_from_slice(&0x20u32.to_le_bytes());
+        data.extend_from_slice(&[
+            0, // augmentation length
+            0x41, 0x0e, 16, 0x86, 2, // +1: cfa rsp+16, rbp at cfa-16
+            0x43, 0x0d, 6, // +3: cfa rbp+16
+            0, 0, 0, 0, 0, 0, 0,
+        ]);
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        CfiSection {
+            kind: SectionKind::EhFrame,
+            address: 0,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_eh_frame_rows() {
+        let table = CfiTable::parse(vec![eh_frame()], 0x5555_0000_0000, 8);
+        assert_eq!(table.fdes.len(), 1);
+        assert_eq!(table.fdes[0].start, 0x1000);
+        assert_eq!(table.fdes[0].end, 0x1020);
+        assert!(table.row(0x5555_0000_2000).is_none());
+
+        let (row, cie) = table.row(0x5555_0000_1000).unwrap();
+        assert_eq!(cie.return_address, 16);
+        assert_eq!(row.cfa, CfaRule::RegisterOffset(7, 8));
+        assert_eq!(row.registers[&16], RegisterRule::Offset(-8));
+        assert!(!row.registers.contains_key(&6));
+
+        let (row, _) = table.row(0x5555_0000_1001).unwrap();
+        assert_eq!(row.cfa, CfaRule::RegisterOffset(7, 16));
+        assert_eq!(row.registers[&6], RegisterRule::Offset(-16));
+
+        let (row, _) = table.row(0x5555_0000_101f).unwrap();
+        assert_eq!(row.cfa, CfaRule::RegisterOffset(6, 16));
+    }
+
+    #[test]
+    fn test_leb128() {
+        let data = [0xe5, 0x8e, 0x26, 0x7f, 0x80, 0x7f];
+        let mut r = Reader::new(&data, 0);
+        assert_eq!(r.uleb(), Some(624485));
+        assert_eq!(r.sleb(), Some(-1));
+        assert_eq!(r.sleb(), Some(-128));
+        assert!(r.at_end());
+    }
+}
@@ -0,0 +1,373 @@
+// decode target values using the analysis types of the live view
+
+use crate::abi::register_name;
+use crate::types::{CoreFunction, CoreType, CoreView};
+use crate::{BNDebugStopReason, DebuggerController, RegisterValue};
+use binaryninjacore_sys as sys;
+use binaryninjacore_sys::{BNTypeClass, BNVariableSourceType};
+use std::fmt;
+
+/// Longest C string read when decoding `char*` values
+const MAX_STRING_LEN: usize = 256;
+/// Largest pointee read when dereferencing pointers
+const MAX_POINTEE_SIZE: u64 = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypedValue {
+    Bool(bool),
+    Integer {
+        value: u64,
+        signed: bool,
+        width: usize,
+    },
+    Float(f64),
+    /// `pointee` is the decoded target, None if it is unreadable or of unknown type
+    Pointer {
+        address: u64,
+        pointee: Option<Box<TypedValue>>,
+    },
+    /// NUL terminated string behind a `char*`
+    String {
+        address: u64,
+        value: String,
+    },
+    /// Structures, arrays and anything else that is not decoded
+    Bytes(Vec<u8>),
+}
+
+impl TypedValue {
+    /// Integer or pointer value
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Self::Bool(b) => Some(b as u64),
+            Self::Integer { value, .. } => Some(value),
+            Self::Pointer { address, .. } | Self::String { address, .. } => Some(address),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TypedValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Integer {
+                value,
+                signed: true,
+                width,
+            } => write!(f, "{}", sign_extend(*value, *width)),
+            Self::Integer { value, .. } => write!(f, "0x{:x}", value),
+            Self::Float(v) => write!(f, "{}", v),
+            Self::Pointer {
+                address,
+                pointee: Some(pointee),
+            } => write!(f, "0x{:x} -> {}", address, pointee),
+            Self::Pointer { address, .. } => write!(f, "0x{:x}", address),
+            Self::String { address, value } => write!(f, "0x{:x} {:?}", address, value),
+            Self::Bytes(bytes) => write!(f, "{:02x?}", bytes),
+        }
+    }
+}
+
+fn sign_extend(value: u64, width: usize) -> i64 {
+    if width == 0 || width >= 8 {
+        return value as i64;
+    }
+    let shift = 64 - width * 8;
+    ((value << shift) as i64) >> shift
+}
+
+fn le_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    let len = bytes.len().min(8);
+    buf[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(buf)
+}
+
+/// Where a variable's value was read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueLocation {
+    Register(String),
+    Stack(u64),
+}
+
+impl fmt::Display for ValueLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Register(name) => write!(f, "{}", name),
+            Self::Stack(address) => write!(f, "[0x{:x}]", address),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionArg {
+    pub name: String,
+    pub type_name: String,
+    pub location: ValueLocation,
+    pub value: TypedValue,
+}
+
+impl fmt::Display for FunctionArg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} ({}) = {}",
+            self.type_name, self.name, self.location, self.value
+        )
+    }
+}
+
+impl DebuggerController {
+    /// Decode raw little-endian bytes as `ty`, following pointers `depth` levels deep
+    pub(crate) fn decode_value(
+        &self,
+        view: &CoreView,
+        ty: CoreType,
+        bytes: &[u8],
+        depth: u32,
+    ) -> TypedValue {
+        let ty = ty.resolve(view.handle());
+        let width = (ty.width() as usize).min(bytes.len());
+        let bytes = &bytes[..width];
+        match ty.class() {
+            BNTypeClass::BoolTypeClass => TypedValue::Bool(bytes.iter().any(|&b| b != 0)),
+            BNTypeClass::IntegerTypeClass
+            | BNTypeClass::EnumerationTypeClass
+            | BNTypeClass::WideCharTypeClass
+                if width <= 8 =>
+            {
+                TypedValue::Integer {
+                    value: le_u64(bytes),
+                    signed: ty.is_signed(),
+                    width,
+                }
+            }
+            BNTypeClass::FloatTypeClass if width == 4 => {
+                TypedValue::Float(f32::from_le_bytes(bytes.try_into().unwrap()) as f64)
+            }
+            BNTypeClass::FloatTypeClass if width == 8 => {
+                TypedValue::Float(f64::from_le_bytes(bytes.try_into().unwrap()))
+            }
+            BNTypeClass::PointerTypeClass => {
+                let address = le_u64(bytes);
+                let Some(target) = ty.child() else {
+                    return TypedValue::Pointer {
+                        address,
+                        pointee: None,
+                    };
+                };
+                let target = target.resolve(view.handle());
+                if target.class() == BNTypeClass::IntegerTypeClass && target.width() == 1 {
+                    if let Some(value) = self.read_c_string(address) {
+                        return TypedValue::String { address, value };
+                    }
+                }
+                let size = target.width();
+                let pointee = (depth > 0 && address != 0 && size > 0 && size <= MAX_POINTEE_SIZE)
+                    .then(|| self.read_memory(address, size as usize))
+                    .flatten()
+                    .map(|data| Box::new(self.decode_value(view, target, &data, depth - 1)));
+                TypedValue::Pointer { address, pointee }
+            }
+            _ => TypedValue::Bytes(bytes.to_vec()),
+        }
+    }
+
+    /// Read a NUL terminated string, None if unreadable
+    pub(crate) fn read_c_string(&self, address: u64) -> Option<String> {
+        if address == 0 {
+            return None;
+        }
+        let read = self.read_memory_partial(address, MAX_STRING_LEN);
+        let first = read.spans.first().filter(|s| s.address == address)?;
+        let end = first
+            .data
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(first.data.len());
+        Some(String::from_utf8_lossy(&first.data[..end]).into_owned())
+    }
+
+    /// Read the bytes of a function variable, only meaningful at the function's entry
+    pub(crate) fn read_variable(
+        &self,
+        func: &CoreFunction,
+        var: &sys::BNVariable,
+        size: usize,
+        entry_sp: u64,
+    ) -> Option<(ValueLocation, Vec<u8>)> {
+        match var.type_ {
+            BNVariableSourceType::RegisterVariableSourceType => {
+                let name = register_name(func.arch(), var.storage as u32)?;
+                let mut bytes = self.get_register_value(&name);
+                bytes.truncate(size.max(1));
+                Some((ValueLocation::Register(name), bytes))
+            }
+            BNVariableSourceType::StackVariableSourceType => {
+                // stack storage is relative to the stack pointer at entry
+                let address = entry_sp.wrapping_add(var.storage as u64);
+                let bytes = self.read_memory(address, size.max(1))?;
+                Some((ValueLocation::Stack(address), bytes))
+            }
+            BNVariableSourceType::FlagVariableSourceType => None,
+        }
+    }
+
+    /// Names, types and values of the arguments of the function at the IP.
+    ///
+    /// Only valid when stopped at the function's entry, before the prologue has
+    /// moved the stack pointer or clobbered argument registers. Pointers are
+    /// dereferenced one level and `char*` arguments are read as strings.
+    pub fn function_args(&self) -> Vec<FunctionArg> {
+        let ip = self.ip();
+        let Some(view) = self.analysis_view(ip) else {
+            return Vec::new();
+        };
+        let Some(func) = CoreFunction::at(&view, ip) else {
+            return Vec::new();
+        };
+        if func.start() != ip {
+            log::warn!(
+                "0x{:x} is not the entry of {}, argument values may be stale",
+                ip,
+                func.name()
+            );
+        }
+
+        let sp = self.sp();
+        let mut args = Vec::new();
+        for var in func.parameter_variables() {
+            let Some(ty) = func.variable_type(&var) else {
+                continue;
+            };
+            let type_name = ty.name();
+            let size = ty.width() as usize;
+            let Some((location, bytes)) = self.read_variable(&func, &var, size, sp) else {
+                continue;
+            };
+            args.push(FunctionArg {
+                name: func.variable_name(&var),
+                type_name,
+                location,
+                value: self.decode_value(&view, ty, &bytes, 1),
+            });
+        }
+        args
+    }
+
+    /// Run until the current function returns and capture its return value.
+    ///
+    /// The return address and the caller's stack pointer come from the return
+    /// address slot or link register when stopped at the entry, otherwise from the
+    /// caller's frame. Hits of the return address by other threads and by
+    /// deeper recursive calls are skipped; only the current thread arriving
+    /// with a stack pointer at or above the caller's counts, arguments popped
+    /// by the callee leave it above. Returns None if the target stops
+    /// anywhere else first (another breakpoint, exit, ...).
+    pub fn finish_and_capture(&self) -> Option<CapturedReturn> {
+        let ip = self.ip();
+        let view = self.analysis_view(ip)?;
+        let func = CoreFunction::at(&view, ip)?;
+        let (return_address, caller_sp) = self.return_site(&func, ip)?;
+
+        // resolve everything about the callee before leaving it
+        let arch = func.arch();
+        let cc = func
+            .calling_convention()
+            .or_else(|| self.calling_convention())?;
+        let ty = func
+            .return_type()
+            .map(|ty| ty.resolve(view.handle()))
+            .filter(|ty| ty.class() != BNTypeClass::VoidTypeClass);
+        let register = match &ty {
+            Some(ty) if ty.class() == BNTypeClass::FloatTypeClass => cc.float_return_register(),
+            _ => cc.integer_return_register(),
+        }
+        .and_then(|reg| register_name(arch, reg));
+
+        let thread = self.active_thread();
+        loop {
+            let reason = self.run_to_and_wait(&[return_address]);
+            if reason == BNDebugStopReason::ProcessExited {
+                return None;
+            }
+            if self.ip() != return_address {
+                log::warn!(
+                    "stopped at 0x{:x} before {} returned",
+                    self.ip(),
+                    func.name()
+                );
+                return None;
+            }
+            // another thread reached it first, keep following ours
+            if self.active_thread().tid != thread.tid {
+                self.set_active_thread(&thread);
+                continue;
+            }
+            // a deeper recursive activation returning leaves the stack lower,
+            // callee-pops conventions (`ret imm16`) leave it above the caller's
+            if self.sp() >= caller_sp {
+                break;
+            }
+        }
+
+        let (type_name, value) = match (ty, register) {
+            (Some(ty), Some(register)) => {
+                let type_name = ty.name();
+                let bytes = self.get_register_value(&register);
+                let value = match RegisterValue::from_bytes(&bytes) {
+                    // x87 st0, decode_value only knows IEEE widths
+                    Some(f80 @ RegisterValue::F80(_)) => TypedValue::Float(f80.as_f64()?),
+                    _ => self.decode_value(&view, ty, &bytes, 1),
+                };
+                (type_name, Some(value))
+            }
+            (Some(ty), None) => (ty.name(), None),
+            (None, _) => ("void".to_owned(), None),
+        };
+        Some(CapturedReturn {
+            function: func.name(),
+            return_address,
+            type_name,
+            value,
+        })
+    }
+
+    /// Return address of the current function and the stack pointer the caller
+    /// will have once it returned
+    fn return_site(&self, func: &CoreFunction, ip: u64) -> Option<(u64, u64)> {
+        if func.start() == ip {
+            let arch = func.arch();
+            let sp = self.sp();
+            let link = unsafe { sys::BNGetArchitectureLinkRegister(arch) };
+            return match register_name(arch, link) {
+                Some(link) => Some((self.reg_u64(&link)?, sp)),
+                // the call pushed the return address, ret pops it
+                None => Some((self.read_pointer(sp)?, sp + self.address_size() as u64)),
+            };
+        }
+        let tid = self.active_thread().tid;
+        let caller = self.frames_of_thread(tid).into_iter().nth(1)?;
+        Some((caller.pc, caller.sp))
+    }
+}
+
+/// Result of `finish_and_capture`
+#[derive(Debug, Clone)]
+pub struct CapturedReturn {
+    pub function: String,
+    pub return_address: u64,
+    pub type_name: String,
+    /// None for void functions or when the return register is unknown
+    pub value: Option<TypedValue>,
+}
+
+impl fmt::Display for CapturedReturn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{} returned {} {}", self.function, self.type_name, value),
+            None => write!(f, "{} returned", self.function),
+        }
+    }
+}